@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wyze_parser::{find_all, parse_hex_line};
+
+// Capture text, as given on the command line or pasted into the web decoder
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let bytes: Vec<u8> = text.lines().flat_map(parse_hex_line).collect();
+        let _ = find_all(&bytes);
+    }
+});
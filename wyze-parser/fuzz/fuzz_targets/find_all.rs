@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wyze_parser::find_all;
+
+// Raw bytes, as decoded from a capture
+fuzz_target!(|data: &[u8]| {
+    for msg in find_all(data) {
+        let _ = msg.decode();
+    }
+});
@@ -0,0 +1,290 @@
+extern crate wyze_protocol;
+
+use wyze_protocol::magic::{self, Frame};
+use wyze_protocol::packet::{PacketPayload, PacketSyncType};
+use wyze_protocol::ParseError;
+
+pub use wyze_protocol::PacketSource;
+
+pub mod observer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use observer::{Format, Printer};
+
+#[derive(Debug)]
+pub struct Msg<'a> {
+    pub source: PacketSource,
+    pub msg_type: PacketSyncType,
+    pub id: u8,
+    pub ack: bool,
+    pub payload: &'a [u8],
+    // The whole frame, preamble through checksum
+    pub raw: &'a [u8],
+}
+
+impl<'a> Msg<'a> {
+    // Decodes the payload the same way the daemon does, for the messages it
+    // understands (alarms, event logs and scan results)
+    pub fn decode(&self) -> Result<PacketPayload, ParseError> {
+        let cmd_type = match self.msg_type {
+            PacketSyncType::Async => 0x53,
+            PacketSyncType::Sync => 0x43,
+        };
+        magic::parse_payload(&Frame {
+            source: self.source,
+            cmd_type,
+            cmd_id: self.id,
+            ack: self.ack,
+            payload: self.payload.to_vec(),
+        })
+    }
+}
+
+// Frames with a type byte other than sync or async are left out
+fn msg<'a>(raw: &'a [u8], frame: &Frame) -> Option<Msg<'a>> {
+    let msg_type = match frame.cmd_type {
+        0x43 => PacketSyncType::Sync,
+        0x53 => PacketSyncType::Async,
+        _ => return None,
+    };
+    // <preamble: 2> <type> <len> <id> <payload> <checksum: 2>
+    let payload = if frame.ack {
+        &raw[..0]
+    } else {
+        &raw[5..raw.len() - 2]
+    };
+    Some(Msg {
+        source: frame.source,
+        msg_type,
+        id: frame.cmd_id,
+        ack: frame.ack,
+        payload,
+        raw,
+    })
+}
+
+// Decodes every message in `data`, skipping any that fail to parse
+pub fn find_all(data: &[u8]) -> Vec<Msg<'_>> {
+    magic::decode_all_raw(data)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|(raw, frame)| msg(raw, &frame))
+        .collect()
+}
+
+// Decodes a pasted capture, in any format the command line tool reads, and
+// returns each frame annotated as `wyze-parser -vvv` would print it
+pub fn annotate(capture: &str) -> String {
+    let data: Vec<u8> = capture.lines().flat_map(parse_hex_line).collect();
+    let printer = Printer::new(Format::Pretty, true);
+    find_all(&data).iter().map(|msg| printer.render(msg)).collect()
+}
+
+// Logged by the daemon before each write, see wyze::link
+const SENDING: &str = "Sending data ";
+
+// Extracts hex bytes from one line of a capture. Handles the daemon's trace
+// log ("Read 63: [3E, 55, ...]" and "Sending data [aa, 55, ...]") and the
+// serial dumps ("55 AA 53 ..."). Lines containing anything else are treated
+// as comments.
+//
+// Read lines are whole HID reports: <count> <data: count> <stale padding>.
+// Only the data is kept, so frames that continue on the next line come out
+// whole and leftovers from earlier reports aren't decoded twice. Sending
+// lines and any other bracketed bytes are frames as written, kept as is.
+pub fn parse_hex_line(line: &str) -> Vec<u8> {
+    let (line, report) = if let Some(array) = report_array(line) {
+        (array, true)
+    } else if let Some(start) = line.find(SENDING) {
+        match array_after(line, start) {
+            Some(array) => (array, false),
+            None => return vec![],
+        }
+    } else {
+        match (line.rfind('['), line.rfind(']')) {
+            (Some(open), Some(close)) if open < close => (&line[open + 1..close], false),
+            _ => (line, false),
+        }
+    };
+
+    let mut bytes = vec![];
+    for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+        if token.is_empty() || token == "->" || token == "<-" {
+            continue;
+        }
+        if token.len() > 2 {
+            return vec![];
+        }
+        match u8::from_str_radix(token, 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return vec![],
+        }
+    }
+    if report && !bytes.is_empty() {
+        let count = bytes.remove(0) as usize;
+        bytes.truncate(count);
+    }
+    bytes
+}
+
+// The bytes of a "Read <len>: [...]" line, logged by the daemon for each
+// report it reads
+fn report_array(line: &str) -> Option<&str> {
+    let start = line.find("Read ")? + "Read ".len();
+    let rest = &line[start..];
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 || !rest[digits..].starts_with(": [") {
+        return None;
+    }
+    array_after(line, start + digits)
+}
+
+// The text between the first brackets at or after `from`
+fn array_after(line: &str, from: usize) -> Option<&str> {
+    let open = from + line[from..].find('[')?;
+    let close = open + line[open..].find(']')?;
+    Some(&line[open + 1..close])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trace lines as simple_logger prints them
+    const SENDING_LINE: &str =
+        "2019-06-24 21:20:57,659 TRACE [wyze::link] Sending data [aa, 55, 43, 03, 27, 01, 6c], attempt 1";
+    const READ_LINE: &str =
+        "2019-06-24 21:20:57,700 TRACE [wyze::link] Read 64: [07, 55, AA, 53, 27, FF, 02, 78, 55, AA, 53, 1D]";
+
+    #[test]
+    fn sending_line_is_a_whole_frame() {
+        assert_eq!(
+            parse_hex_line(SENDING_LINE),
+            vec![0xAA, 0x55, 0x43, 0x03, 0x27, 0x01, 0x6C]
+        );
+
+        let data = parse_hex_line(SENDING_LINE);
+        let msgs = find_all(&data);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].source, PacketSource::Host);
+        assert_eq!(msgs[0].id, 0x27);
+    }
+
+    #[test]
+    fn read_line_is_trimmed_to_its_count() {
+        assert_eq!(
+            parse_hex_line(READ_LINE),
+            vec![0x55, 0xAA, 0x53, 0x27, 0xFF, 0x02, 0x78]
+        );
+    }
+
+    #[test]
+    fn short_arrays_keep_all_their_bytes() {
+        // Not a report, so the first byte isn't a count
+        assert_eq!(parse_hex_line("[02, 55, AA]"), vec![0x02, 0x55, 0xAA]);
+        // A count past the end of a report keeps what is there
+        assert_eq!(parse_hex_line("Read 3: [3E, 55, AA]"), vec![0x55, 0xAA]);
+    }
+
+    // An alarm from a contact sensor, 55 AA through the checksum
+    const ALARM: &[u8] = &[
+        0x55, 0xAA, 0x53, 0x1D, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA2,
+        0x37, 0x37, 0x37, 0x42, 0x31, 0x39, 0x36, 0x32, 0x01, 0x1A, 0x60, 0x00, 0x01, 0x00,
+        0x00, 0x52, 0x44, 0x04, 0xF5,
+    ];
+
+    // The alarm cut across two reports, with stale bytes after each count
+    const SPLIT_CAPTURE: &str = "\
+Read 64: [14, 55, AA, 53, 1D, 19, 00, 00, 00, 00, 00, 00, 00, 00, A2, 37, 37, 37, 42, 31, 39, 55, AA]
+Read 64: [0D, 36, 32, 01, 1A, 60, 00, 01, 00, 00, 52, 44, 04, F5, 1D, 19, 00]
+";
+
+    fn lines(capture: &str) -> Vec<u8> {
+        capture.lines().flat_map(parse_hex_line).collect()
+    }
+
+    #[test]
+    fn serial_dump_line() {
+        let line = "55 AA 53 1D 19 00 00 00 00 00 00 00 00 A2 37 37 37 42 31 39 36 32 01 1A 60 00 01 00 00 52 44 04 F5";
+        assert_eq!(parse_hex_line(line), ALARM);
+        assert_eq!(
+            parse_hex_line("-> AA 55 43 03 27 01 6C"),
+            vec![0xAA, 0x55, 0x43, 0x03, 0x27, 0x01, 0x6C]
+        );
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        assert!(parse_hex_line("# pairing a contact sensor").is_empty());
+        assert!(parse_hex_line("2019-06-24 21:20:57,659 INFO  [wyze] Opened hub").is_empty());
+        assert!(parse_hex_line("").is_empty());
+    }
+
+    #[test]
+    fn frame_split_across_lines() {
+        assert_eq!(lines(SPLIT_CAPTURE), ALARM);
+
+        let data = lines(SPLIT_CAPTURE);
+        let msgs = find_all(&data);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].raw, ALARM);
+    }
+
+    #[test]
+    fn msg_fields() {
+        let mut data = ALARM.to_vec();
+        // The bridge acking 0x27
+        data.extend_from_slice(&[0x55, 0xAA, 0x53, 0x27, 0xFF, 0x02, 0x78]);
+        let msgs = find_all(&data);
+        assert_eq!(msgs.len(), 2);
+
+        let alarm = &msgs[0];
+        assert_eq!(alarm.source, PacketSource::Bridge);
+        assert_eq!(alarm.msg_type, PacketSyncType::Async);
+        assert_eq!(alarm.id, 0x19);
+        assert!(!alarm.ack);
+        assert_eq!(alarm.payload, &ALARM[5..ALARM.len() - 2]);
+        match alarm.decode() {
+            Ok(PacketPayload::SensorAlarm(alarm)) => {
+                assert_eq!(alarm.mac, "777B1962");
+                assert_eq!(alarm.counter, 0x52);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let ack = &msgs[1];
+        assert!(ack.ack);
+        assert_eq!(ack.id, 0x27);
+        assert!(ack.payload.is_empty());
+        assert_eq!(ack.decode(), Err(ParseError::Ack(0x27)));
+    }
+
+    #[test]
+    fn find_all_skips_bad_frames() {
+        let mut data = vec![0x13, 0x37];
+        let mut corrupt = ALARM.to_vec();
+        corrupt[20] ^= 0x01;
+        data.extend(corrupt);
+        data.extend_from_slice(ALARM);
+
+        let msgs = find_all(&data);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].raw, ALARM);
+    }
+
+    #[test]
+    fn annotate_capture() {
+        let out = annotate(SPLIT_CAPTURE);
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("id: 19, ack: false, payload: [00, 00"));
+        assert!(lines[1].contains("SensorAlarm"));
+        assert!(lines[1].contains("777B1962"));
+        assert_eq!(
+            lines[2],
+            "    raw: 55 AA 53 1D 19 00 00 00 00 00 00 00 00 A2 37 37 37 42 31 39 36 32 01 1A 60 00 01 00 00 52 44 04 F5"
+        );
+        assert!(annotate("# nothing here").is_empty());
+    }
+}
@@ -1,92 +1,110 @@
-extern crate nom;
-extern crate num;
-#[macro_use]
-extern crate num_derive;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 
-use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::bytes::complete::take;
-use nom::multi::many_till;
-use nom::number::complete::{be_u16, be_u8};
-use nom::IResult;
+use wyze_parser::observer::{Format, FrameObserver, Only, Printer, Verbosity};
+use wyze_parser::{find_all, parse_hex_line};
 
-use num::FromPrimitive;
+struct Options {
+    format: Format,
+    only: Option<Vec<u8>>,
+    verbosity: u8,
+    inputs: Vec<String>,
+}
 
-#[derive(Debug)]
-pub enum PacketSource {
-    Bridge, // 55 AA
-    Host,   // AA 55
+fn usage() -> ! {
+    eprintln!("Usage: wyze-parser [-v|-vv|-vvv] [--format pretty|json|csv|raw-annotated] [--only cmd=0x19,0x35] [FILE|-]...");
+    eprintln!();
+    eprintln!("  -v    sensor events (default)");
+    eprintln!("  -vv   events, acks and other protocol messages");
+    eprintln!("  -vvv  everything, plus the raw bytes of each frame");
+    std::process::exit(2);
 }
 
-#[derive(Debug, FromPrimitive)]
-pub enum PacketType {
-    Async = 0x53,
-    Sync = 0x43,
+fn parse_cmd_id(s: &str) -> Option<u8> {
+    let s = s.trim();
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u8::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
 }
 
-// const MSG: &[u8] = &[
-//     0x55, 0xAA, 0x43, 0xB, 0x5, 0x37, 0x37, 0x37, 0x41, 0x46, 0x39, 0x42, 0x46, 0x3, 0x3F, 0x5,
-//     0x37, 0x37, 0x37, 0x41, 0x46, 0x39, 0x42, 0x46, 0x3, 0x3F,
-// ];
+fn parse_args() -> Options {
+    let mut options = Options {
+        format: Format::Pretty,
+        only: None,
+        verbosity: 1,
+        inputs: vec![],
+    };
 
-// const MSG: &[u8] = &[0x55, 0xaa, 0x53, 0x16, 0xff, 0x02, 0x67];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                options.format = match args.next().as_deref() {
+                    Some("pretty") => Format::Pretty,
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    Some("raw-annotated") => Format::RawAnnotated,
+                    _ => usage(),
+                }
+            }
+            "--only" => {
+                let filter = args.next().unwrap_or_else(|| usage());
+                if !filter.starts_with("cmd=") {
+                    usage();
+                }
+                let ids: Option<Vec<u8>> = filter[4..].split(',').map(parse_cmd_id).collect();
+                options.only = Some(ids.unwrap_or_else(|| usage()));
+            }
+            "-v" => options.verbosity = 1,
+            "-vv" => options.verbosity = 2,
+            "-vvv" => options.verbosity = 3,
+            "-h" | "--help" => usage(),
+            _ => options.inputs.push(arg),
+        }
+    }
 
-const MSG: &[u8] = &[0x55, 0xAA, 0x53, 0x1C, 0x17, 0x30, 0x2E, 0x30, 0x2E, 0x30, 0x2E, 0x33, 0x30, 0x20, 0x56, 0x31, 0x2E, 0x34, 0x20, 0x44, 0x6F, 0x6E, 0x67, 0x6C, 0x65, 0x20, 0x55, 0x44, 0x33, 0x55, 0x7, 0xC5, 0x0, 0x0, 0x0, 0xA2, 0x37, 0x37, 0x37, 0x41, 0x43, 0x32, 0x36, 0x30, 0x2, 0x14, 0x63, 0x0, 0x1, 0x1, 0x2, 0xA3, 0x33, 0x5, 0x3E];
+    if options.inputs.is_empty() {
+        options.inputs.push("-".to_string());
+    }
+    options
+}
 
-fn find_msg(input: &[u8]) -> IResult<&[u8], PacketSource> {
-    let (input, (_, preamble)) = many_till(
-        take(1 as usize),
-        alt((tag([0x55, 0xAA]), tag([0xAA, 0x55]))),
-    )(input)?;
-    let source = if preamble[0] == 0x55 {
-        PacketSource::Bridge
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
     } else {
-        PacketSource::Host
+        Box::new(BufReader::new(File::open(path)?))
     };
-    let (remaining, type_raw) = take(1 as usize)(input)?;
-    let _msg_type = PacketType::from_u8(type_raw[0])
-        .ok_or(nom::Err::Failure((remaining, nom::error::ErrorKind::IsNot)))?;
-    let (remaining, length_or_id) = be_u8(remaining)?;
-    let (remaining, ack_or_id) = be_u8(remaining)?;
-    let length;
-    let id;
-    let ack;
 
-    if ack_or_id == 0xFF {
-        ack = true;
-        id = length_or_id;
-        length = 3;
-    } else {
-        ack = false;
-        id = ack_or_id;
-        length = length_or_id;
+    let mut data = vec![];
+    for line in reader.lines() {
+        data.extend(parse_hex_line(&line?));
     }
+    Ok(data)
+}
 
-    if length < 2 {
-        return Err(nom::Err::Failure((remaining, nom::error::ErrorKind::IsNot)));
-    }
+fn main() {
+    let options = parse_args();
 
-    let (remaining, payload) = take(length - 3)(remaining)?;
-    let (remaining, chksum_msg) = be_u16(remaining)?;
+    let printer = Printer::new(options.format, options.verbosity >= 3);
+    let mut observer: Box<dyn FrameObserver> = match options.only {
+        Some(ids) => Box::new(Verbosity::new(options.verbosity, Only::new(ids, printer))),
+        None => Box::new(Verbosity::new(options.verbosity, printer)),
+    };
 
-    let mut chksum_calc: u16 = 0xFF; // Start at 0x00FF to account for the preamble that we dropped earlier
-    for i in 0..(length) {
-        chksum_calc = chksum_calc.wrapping_add(input[i as usize] as u16);
-    }
+    for input in &options.inputs {
+        let data = match read_input(input) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("{}: {}", input, e);
+                continue;
+            }
+        };
 
-    if chksum_calc != chksum_msg {
-        println!(
-            "Got msg chksum: {:04X?}, calced: {:04X?}",
-            chksum_msg, chksum_calc
-        );
-        return Err(nom::Err::Failure((remaining, nom::error::ErrorKind::IsNot)));
+        for msg in find_all(&data) {
+            observer.frame(&msg);
+        }
     }
-    println!("id: {:02X}, ack: {:?}, payload: {:02X?}", id, ack, payload);
-
-    // TODO: Return something actually useful from the parsing
-    Ok((remaining, source))
-}
-
-fn main() {
-    println!("{:02X?}", find_msg(MSG));
 }
@@ -0,0 +1,271 @@
+use std::convert::TryFrom;
+
+use wyze_protocol::codec::hex;
+use wyze_protocol::packet::PacketPayload;
+use wyze_protocol::{CommandId, ParseError};
+
+use crate::Msg;
+
+// Unsolicited notifications coming from sensors
+const EVENT_IDS: &[CommandId] = &[CommandId::Alarm, CommandId::SensorScan, CommandId::EventLog];
+
+// Receives every message decoded from the input, in order
+pub trait FrameObserver {
+    fn frame(&mut self, msg: &Msg);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Pretty,
+    Json,
+    Csv,
+    RawAnnotated,
+}
+
+// Prints each message to stdout in the selected format
+pub struct Printer {
+    format: Format,
+    show_raw: bool,
+}
+
+impl Printer {
+    pub fn new(format: Format, show_raw: bool) -> Printer {
+        if format == Format::Csv {
+            if show_raw {
+                println!("source,type,id,ack,payload,raw");
+            } else {
+                println!("source,type,id,ack,payload");
+            }
+        }
+        Printer { format, show_raw }
+    }
+}
+
+impl Printer {
+    // The text frame() prints for `msg`, one or more lines
+    pub fn render(&self, msg: &Msg) -> String {
+        let mut out = String::new();
+        match self.format {
+            Format::Pretty => {
+                out += &format!(
+                    "id: {:02X}, ack: {:?}, payload: {:02X?}\n",
+                    msg.id, msg.ack, msg.payload
+                );
+                match msg.decode() {
+                    // The id and payload are on the line above already
+                    Ok(PacketPayload::Unknown { .. }) => (),
+                    Ok(payload) => out += &format!("    {:?}\n", payload),
+                    Err(ParseError::Ack(_)) => (),
+                    Err(e) => out += &format!("    can't decode: {}\n", e),
+                }
+                if self.show_raw {
+                    out += &format!("    raw: {}\n", hex(msg.raw, " "));
+                }
+            }
+            Format::Json => {
+                let raw = if self.show_raw {
+                    format!(",\"raw\":\"{}\"", hex(msg.raw, ""))
+                } else {
+                    String::new()
+                };
+                out += &format!(
+                    "{{\"source\":\"{:?}\",\"type\":\"{:?}\",\"id\":{},\"ack\":{},\"payload\":\"{}\"{}}}\n",
+                    msg.source,
+                    msg.msg_type,
+                    msg.id,
+                    msg.ack,
+                    hex(msg.payload, ""),
+                    raw
+                )
+            }
+            Format::Csv => {
+                let raw = if self.show_raw {
+                    format!(",{}", hex(msg.raw, ""))
+                } else {
+                    String::new()
+                };
+                out += &format!(
+                    "{:?},{:?},0x{:02X},{},{}{}\n",
+                    msg.source,
+                    msg.msg_type,
+                    msg.id,
+                    msg.ack,
+                    hex(msg.payload, ""),
+                    raw
+                )
+            }
+            Format::RawAnnotated => {
+                out += &format!(
+                    "{}    ; {:?} {:?} id=0x{:02X}{}\n",
+                    hex(msg.raw, " "),
+                    msg.source,
+                    msg.msg_type,
+                    msg.id,
+                    if msg.ack { " ack" } else { "" }
+                )
+            }
+        }
+        out
+    }
+}
+
+impl FrameObserver for Printer {
+    fn frame(&mut self, msg: &Msg) {
+        print!("{}", self.render(msg));
+    }
+}
+
+// Passes on only messages with one of the given command ids
+pub struct Only<O> {
+    ids: Vec<u8>,
+    inner: O,
+}
+
+impl<O: FrameObserver> Only<O> {
+    pub fn new(ids: Vec<u8>, inner: O) -> Only<O> {
+        Only { ids, inner }
+    }
+}
+
+impl<O: FrameObserver> FrameObserver for Only<O> {
+    fn frame(&mut self, msg: &Msg) {
+        if self.ids.contains(&msg.id) {
+            self.inner.frame(msg);
+        }
+    }
+}
+
+// -v:   sensor events only
+// -vv:  adds acks and the rest of the protocol chatter
+// -vvv: adds the raw frame bytes (see Printer::show_raw)
+pub struct Verbosity<O> {
+    level: u8,
+    inner: O,
+}
+
+impl<O: FrameObserver> Verbosity<O> {
+    pub fn new(level: u8, inner: O) -> Verbosity<O> {
+        Verbosity { level, inner }
+    }
+}
+
+impl<O: FrameObserver> FrameObserver for Verbosity<O> {
+    fn frame(&mut self, msg: &Msg) {
+        let is_event = !msg.ack
+            && matches!(CommandId::try_from(msg.id), Ok(id) if EVENT_IDS.contains(&id));
+        if is_event || self.level >= 2 {
+            self.inner.frame(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_all;
+
+    // An alarm, the bridge acking 0x27 and an inquiry response (0x28)
+    const CAPTURE: &[u8] = &[
+        0x55, 0xAA, 0x53, 0x1D, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA2,
+        0x37, 0x37, 0x37, 0x42, 0x31, 0x39, 0x36, 0x32, 0x01, 0x1A, 0x60, 0x00, 0x01, 0x00,
+        0x00, 0x52, 0x44, 0x04, 0xF5, 0x55, 0xAA, 0x53, 0x27, 0xFF, 0x02, 0x78, 0x55, 0xAA,
+        0x53, 0x05, 0x28, 0x01, 0x02, 0x01, 0x82,
+    ];
+
+    const ALARM_PAYLOAD: &str = "0000000000000000A23737374231393632011A60000100005244";
+    const ALARM_RAW: &str = "55 AA 53 1D 19 00 00 00 00 00 00 00 00 A2 37 37 37 42 31 39 36 32 01 1A 60 00 01 00 00 52 44 04 F5";
+
+    fn render(format: Format, show_raw: bool) -> Vec<String> {
+        let printer = Printer::new(format, show_raw);
+        find_all(CAPTURE).iter().map(|msg| printer.render(msg)).collect()
+    }
+
+    #[derive(Default)]
+    struct Ids(Vec<u8>);
+
+    impl FrameObserver for &mut Ids {
+        fn frame(&mut self, msg: &Msg) {
+            self.0.push(msg.id);
+        }
+    }
+
+    fn observe<O: FrameObserver>(mut observer: O) {
+        for msg in find_all(CAPTURE) {
+            observer.frame(&msg);
+        }
+    }
+
+    #[test]
+    fn pretty() {
+        let out = render(Format::Pretty, false);
+        let alarm: Vec<_> = out[0].lines().collect();
+        assert_eq!(alarm.len(), 2);
+        assert!(alarm[0].starts_with("id: 19, ack: false, payload: [00, 00,"));
+        assert!(alarm[1].starts_with("    SensorAlarm(SensorAlarmPacket {"));
+        assert_eq!(out[1], "id: 27, ack: true, payload: []\n");
+        // Not decoded, so nothing below the id and payload
+        assert_eq!(out[2], "id: 28, ack: false, payload: [01, 02]\n");
+
+        let out = render(Format::Pretty, true);
+        assert!(out[0].ends_with(&format!("    raw: {}\n", ALARM_RAW)));
+    }
+
+    #[test]
+    fn json() {
+        let out = render(Format::Json, false);
+        assert_eq!(
+            out[0],
+            format!(
+                "{{\"source\":\"Bridge\",\"type\":\"Async\",\"id\":25,\"ack\":false,\"payload\":\"{}\"}}\n",
+                ALARM_PAYLOAD
+            )
+        );
+        assert_eq!(
+            out[1],
+            "{\"source\":\"Bridge\",\"type\":\"Async\",\"id\":39,\"ack\":true,\"payload\":\"\"}\n"
+        );
+
+        let out = render(Format::Json, true);
+        assert!(out[1].ends_with(",\"raw\":\"55AA5327FF0278\"}\n"));
+    }
+
+    #[test]
+    fn csv() {
+        let out = render(Format::Csv, false);
+        assert_eq!(out[0], format!("Bridge,Async,0x19,false,{}\n", ALARM_PAYLOAD));
+        assert_eq!(out[1], "Bridge,Async,0x27,true,\n");
+
+        let out = render(Format::Csv, true);
+        assert_eq!(out[2], "Bridge,Async,0x28,false,0102,55AA53052801020182\n");
+    }
+
+    #[test]
+    fn raw_annotated() {
+        let out = render(Format::RawAnnotated, false);
+        assert_eq!(out[0], format!("{}    ; Bridge Async id=0x19\n", ALARM_RAW));
+        assert_eq!(out[1], "55 AA 53 27 FF 02 78    ; Bridge Async id=0x27 ack\n");
+    }
+
+    #[test]
+    fn verbosity() {
+        let mut ids = Ids::default();
+        observe(Verbosity::new(1, &mut ids));
+        assert_eq!(ids.0, vec![0x19]);
+
+        let mut ids = Ids::default();
+        observe(Verbosity::new(2, &mut ids));
+        assert_eq!(ids.0, vec![0x19, 0x27, 0x28]);
+    }
+
+    #[test]
+    fn only() {
+        let mut ids = Ids::default();
+        observe(Only::new(vec![0x27, 0x28], &mut ids));
+        assert_eq!(ids.0, vec![0x27, 0x28]);
+
+        // Filters stack the way main() builds them
+        let mut ids = Ids::default();
+        observe(Verbosity::new(1, Only::new(vec![0x19, 0x28], &mut ids)));
+        assert_eq!(ids.0, vec![0x19]);
+    }
+}
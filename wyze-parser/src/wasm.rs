@@ -0,0 +1,13 @@
+// Entry point for decoding in the browser. Build with
+//
+//     cargo build --lib --target wasm32-unknown-unknown --features wasm
+//
+// and generate the JS bindings with wasm-bindgen.
+
+use wasm_bindgen::prelude::*;
+
+// See crate::annotate()
+#[wasm_bindgen]
+pub fn decode(capture: &str) -> String {
+    crate::annotate(capture)
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wyze_protocol::magic::{decode_all, parse_payload};
+
+// One standalone buffer, as sent in a client's decode request
+fuzz_target!(|data: &[u8]| {
+    for frame in decode_all(data).iter().flatten() {
+        let _ = parse_payload(frame);
+    }
+});
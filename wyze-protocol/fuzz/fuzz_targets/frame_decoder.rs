@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wyze_protocol::magic::parse_payload;
+use wyze_protocol::FrameDecoder;
+
+// A run of 64-byte HID reports, as read from the dongle
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = FrameDecoder::default();
+    for report in data.chunks(64) {
+        decoder.feed_report(report);
+        while let Some(frame) = decoder.next_frame() {
+            let _ = parse_payload(&frame);
+        }
+    }
+});
@@ -0,0 +1,104 @@
+// Frame checksum helpers.
+//
+// Every frame ends in a big-endian u16 that is the wrapping sum of all bytes
+// before it, preamble included:
+//
+//   55 AA 53 1C FF | 02 6D    (0x55 + 0xAA + 0x53 + 0x1C + 0xFF = 0x026D)
+//
+// Both preambles (55 AA and AA 55) add up to 0xFF, so code that has already
+// stripped the preamble can seed the sum with PREAMBLE_SUM instead.
+
+use crate::packet::{Packable, Packet, PacketSyncType};
+
+/// Sum of either preamble, for checksumming a frame without its preamble
+pub const PREAMBLE_SUM: u16 = 0x55 + 0xAA;
+
+#[derive(Debug, PartialEq)]
+pub enum ChecksumError {
+    /// Frame is too short to contain a checksum
+    TooShort,
+    /// Checksum in the frame doesn't match the one computed over it
+    Mismatch { expected: u16, actual: u16 },
+}
+
+/// Computes the checksum of `frame`, which must start at the preamble and
+/// stop before the checksum bytes.
+pub fn checksum(frame: &[u8]) -> u16 {
+    checksum_seeded(0, frame)
+}
+
+/// Continues a checksum from `seed`. Use `PREAMBLE_SUM` as the seed when
+/// `data` starts after the preamble.
+pub fn checksum_seeded(seed: u16, data: &[u8]) -> u16 {
+    data.iter().fold(seed, |acc, x| acc.wrapping_add(*x as u16))
+}
+
+/// Verifies a complete frame, preamble through checksum.
+pub fn verify(frame: &[u8]) -> Result<(), ChecksumError> {
+    if frame.len() < 2 {
+        return Err(ChecksumError::TooShort);
+    }
+
+    let (body, ck) = frame.split_at(frame.len() - 2);
+    let expected = (ck[0] as u16) << 8 | ck[1] as u16;
+    let actual = checksum(body);
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(ChecksumError::Mismatch { expected, actual })
+    }
+}
+
+/// Formats bytes as two upper-case hex digits each, with `separator` in
+/// between: `hex(&[0x55, 0xAA], " ")` is "55 AA".
+pub fn hex(data: &[u8], separator: &str) -> String {
+    data.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Frames a packet sent to the bridge: AA 55 <type> <len> <id> <payload>
+/// <checksum: 2>
+pub fn encode<P>(packet: &P) -> Vec<u8>
+    where P: Packet + Packable
+{
+    frame(&[0xAA, 0x55], packet)
+}
+
+/// Frames a packet as the bridge sends it, starting with 55 AA. For
+/// synthesizing and replaying bridge traffic.
+pub fn encode_bridge<P>(packet: &P) -> Vec<u8>
+    where P: Packet + Packable
+{
+    frame(&[0x55, 0xAA], packet)
+}
+
+fn frame<P>(preamble: &[u8], packet: &P) -> Vec<u8>
+    where P: Packet + Packable
+{
+    let mut write: Vec<u8> = Vec::new();
+    let data = packet.to_bytes();
+
+    // Direction
+    write.extend(preamble);
+
+    // Type
+    match packet.get_packet_type() {
+        PacketSyncType::Sync => write.push(0x43),
+        PacketSyncType::Async => write.push(0x53),
+    }
+
+    // Length
+    write.push(data.len() as u8 + 2);
+
+    // payload
+    write.extend(data);
+
+    // checksum
+    let ck = checksum(&write);
+    let ck_bytes: &[u8] = &[(ck >> 8 & 0xFF) as u8, (ck & 0xFF) as u8];
+    write.extend(ck_bytes);
+
+    write
+}
@@ -0,0 +1,100 @@
+// Every command and response id known so far. A response's id is the id of
+// the command it answers plus one; messages the bridge sends unprompted
+// (alarms, scan results, event logs, time sync requests) have no command.
+
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CommandId {
+    GetEnr = 0x02,
+    GetEnrResponse = 0x03,
+    GetMac = 0x04,
+    GetMacResponse = 0x05,
+    GetKey = 0x06,
+    GetKeyResponse = 0x07,
+    SetCh554Upgrade = 0x0E,
+    UpdateCc1310 = 0x12,
+    Auth = 0x14,
+    AuthResponse = 0x15,
+    GetVersion = 0x16,
+    GetVersionResponse = 0x17,
+    Alarm = 0x19,
+    StartStopNetwork = 0x1C,
+    StartStopNetworkResponse = 0x1D,
+    // A sensor found in join mode, and the command that adds one
+    SensorScan = 0x20,
+    SetRandom = 0x21,
+    SetRandomResponse = 0x22,
+    VerifySensor = 0x23,
+    VerifySensorResponse = 0x24,
+    DeleteSensor = 0x25,
+    DeleteSensorResponse = 0x26,
+    Inquiry = 0x27,
+    InquiryResponse = 0x28,
+    GetSensorCount = 0x2E,
+    GetSensorCountResponse = 0x2F,
+    GetSensorList = 0x30,
+    GetSensorListResponse = 0x31,
+    SyncTime = 0x32,
+    SyncTimeResponse = 0x33,
+    EventLog = 0x35,
+    // Sent in place of an id to acknowledge an async command
+    Ack = 0xFF,
+}
+
+impl CommandId {
+    // The id that answers this one, if it is known
+    pub fn response(self) -> Option<CommandId> {
+        CommandId::try_from(u8::from(self).wrapping_add(1)).ok()
+    }
+}
+
+impl From<CommandId> for u8 {
+    fn from(id: CommandId) -> u8 {
+        id as u8
+    }
+}
+
+// Fails with the raw id for ids that aren't known
+impl TryFrom<u8> for CommandId {
+    type Error = u8;
+
+    fn try_from(raw: u8) -> Result<CommandId, u8> {
+        match raw {
+            0x02 => Ok(CommandId::GetEnr),
+            0x03 => Ok(CommandId::GetEnrResponse),
+            0x04 => Ok(CommandId::GetMac),
+            0x05 => Ok(CommandId::GetMacResponse),
+            0x06 => Ok(CommandId::GetKey),
+            0x07 => Ok(CommandId::GetKeyResponse),
+            0x0E => Ok(CommandId::SetCh554Upgrade),
+            0x12 => Ok(CommandId::UpdateCc1310),
+            0x14 => Ok(CommandId::Auth),
+            0x15 => Ok(CommandId::AuthResponse),
+            0x16 => Ok(CommandId::GetVersion),
+            0x17 => Ok(CommandId::GetVersionResponse),
+            0x19 => Ok(CommandId::Alarm),
+            0x1C => Ok(CommandId::StartStopNetwork),
+            0x1D => Ok(CommandId::StartStopNetworkResponse),
+            0x20 => Ok(CommandId::SensorScan),
+            0x21 => Ok(CommandId::SetRandom),
+            0x22 => Ok(CommandId::SetRandomResponse),
+            0x23 => Ok(CommandId::VerifySensor),
+            0x24 => Ok(CommandId::VerifySensorResponse),
+            0x25 => Ok(CommandId::DeleteSensor),
+            0x26 => Ok(CommandId::DeleteSensorResponse),
+            0x27 => Ok(CommandId::Inquiry),
+            0x28 => Ok(CommandId::InquiryResponse),
+            0x2E => Ok(CommandId::GetSensorCount),
+            0x2F => Ok(CommandId::GetSensorCountResponse),
+            0x30 => Ok(CommandId::GetSensorList),
+            0x31 => Ok(CommandId::GetSensorListResponse),
+            0x32 => Ok(CommandId::SyncTime),
+            0x33 => Ok(CommandId::SyncTimeResponse),
+            0x35 => Ok(CommandId::EventLog),
+            0xFF => Ok(CommandId::Ack),
+            _ => Err(raw),
+        }
+    }
+}
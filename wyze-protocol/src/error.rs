@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// Not a command id with a known payload
+    UnknownCommand(u8),
+    /// An ack, which carries no payload
+    Ack(u8),
+    /// The checksum at the end of the frame doesn't match its contents
+    Checksum,
+    /// The declared length doesn't cover the id and checksum
+    LengthTooShort(u8),
+    /// The declared length is longer than any real frame
+    LengthTooLong(u8),
+    /// The frame or payload ended before all of its fields
+    Truncated { cmd_id: Option<u8> },
+    /// A sensor mac that isn't valid UTF-8
+    InvalidMac(Vec<u8>),
+    /// The payload carries a record other than the one that can be decoded,
+    /// e.g. an event log entry that isn't a sensor event
+    UnexpectedRecord { cmd_id: u8, record: u8 },
+    /// Anything else that doesn't look like a frame
+    Malformed,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(cmd_id) => write!(f, "unknown command {:02X}", cmd_id),
+            ParseError::Ack(cmd_id) => write!(f, "{:02X} ack has no payload", cmd_id),
+            ParseError::Checksum => write!(f, "checksum mismatch"),
+            ParseError::LengthTooShort(len) => write!(f, "declared length {:02X} too short", len),
+            ParseError::LengthTooLong(len) => write!(f, "declared length {:02X} too long", len),
+            ParseError::Truncated { cmd_id: Some(cmd_id) } => {
+                write!(f, "{:02X} payload truncated", cmd_id)
+            }
+            ParseError::Truncated { cmd_id: None } => write!(f, "incomplete frame"),
+            ParseError::InvalidMac(mac) => write!(f, "invalid mac {:02X?}", mac),
+            ParseError::UnexpectedRecord { cmd_id, record } => {
+                write!(f, "unexpected record {:02X} in {:02X}", record, cmd_id)
+            }
+            ParseError::Malformed => write!(f, "malformed frame"),
+        }
+    }
+}
+
+impl Error for ParseError {}
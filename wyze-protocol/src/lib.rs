@@ -0,0 +1,18 @@
+// Wire format of the Wyze sense bridge: packet definitions, framing and
+// checksums, and decoding of what the bridge sends. Shared by the daemon
+// (wyze) and the capture decoder (wyze-parser). Nothing here touches USB.
+
+extern crate log;
+#[macro_use]
+extern crate nom;
+
+pub mod codec;
+pub mod command;
+mod error;
+pub mod magic;
+pub mod packet;
+pub mod sensor;
+
+pub use command::CommandId;
+pub use error::ParseError;
+pub use magic::{Frame, FrameDecoder, PacketSource};
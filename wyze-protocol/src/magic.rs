@@ -0,0 +1,556 @@
+use log::{debug, info, trace, warn};
+use nom::IResult;
+
+use std::convert::TryFrom;
+
+use crate::codec;
+use crate::command::CommandId;
+use crate::error::ParseError;
+use crate::packet::{
+    AlarmRecord, PacketPayload, SensorAlarmPacket, SensorEvent, SensorEventPacket,
+    SensorJoinPacket, SensorScanPacket,
+};
+use crate::sensor::{self, SensorType};
+
+const BRIDGE_PREAMBLE: &[u8] = &[0x55, 0xAA];
+const HOST_PREAMBLE: &[u8] = &[0xAA, 0x55];
+
+// nom error codes: a frame with a bad checksum, a declared length that is
+// too short to cover the header or too long to be a real frame, and a mac
+// that isn't UTF-8. These are turned into ParseErrors before leaving this
+// module.
+const ERR_CHECKSUM: u32 = 1;
+const ERR_TOO_SHORT: u32 = 2;
+const ERR_TOO_LONG: u32 = 3;
+const ERR_MAC: u32 = 4;
+
+// The length byte counts <id> <payload> <checksum>, so anything under 3
+// can't be a frame
+const MIN_FRAME_LEN: u8 = 3;
+// The longest frame seen in captures declares 0x23. Rejecting anything much
+// longer up front keeps a corrupt length byte from holding the decoder up
+// waiting for bytes that will never come.
+pub const MAX_FRAME_LEN: u8 = 0x40;
+
+// Which side sent a frame, told apart by the order of the preamble bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PacketSource {
+    Bridge, // 55 AA
+    Host,   // AA 55
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub source: PacketSource,
+    pub cmd_type: u8,
+    pub cmd_id: u8,
+    pub ack: bool,
+    pub payload: Vec<u8>,
+}
+
+fn is_preamble(w: &[u8]) -> bool {
+    w == BRIDGE_PREAMBLE || w == HOST_PREAMBLE
+}
+
+// Skips to just past the next preamble, returning who it says sent the frame
+fn skip_preamble(msg: &[u8]) -> IResult<&[u8], PacketSource> {
+    match msg.windows(2).position(is_preamble) {
+        Some(pos) if msg[pos] == HOST_PREAMBLE[0] => Ok((&msg[pos + 2..], PacketSource::Host)),
+        Some(pos) => Ok((&msg[pos + 2..], PacketSource::Bridge)),
+        None => Err(nom::Err::Incomplete(nom::Needed::Size(2))),
+    }
+}
+
+// 55 AA <type> <len> <id> <payload: len - 3> <checksum: 2>
+// 55 AA <type> <id> FF <checksum: 2>
+//
+// The dongle sometimes echoes host frames back, which look the same but
+// start with AA 55. Those come out with PacketSource::Host.
+//
+// A frame whose checksum doesn't match fails with ERR_CHECKSUM, with the
+// input positioned just past it.
+fn parse_frame(msg: &[u8]) -> IResult<&[u8], Frame> {
+    let (msg, source) = skip_preamble(msg)?;
+    let body = msg;
+    let (msg, cmd_type) = nom::be_u8(msg)?;
+    let at_length = msg;
+    let (msg, length_or_id) = nom::be_u8(msg)?;
+    let (msg, ack_or_id) = nom::be_u8(msg)?;
+
+    if ack_or_id == u8::from(CommandId::Ack) {
+        let (msg, checksum) = nom::be_u16(msg)?;
+        check(msg, &body[..3], checksum)?;
+        return Ok((
+            msg,
+            Frame {
+                source,
+                cmd_type,
+                cmd_id: length_or_id,
+                ack: true,
+                payload: vec![],
+            },
+        ));
+    }
+
+    // These fail at the length byte, so frame_error() can report it
+    if length_or_id < MIN_FRAME_LEN {
+        return error(at_length, ERR_TOO_SHORT);
+    }
+    if length_or_id > MAX_FRAME_LEN {
+        return error(at_length, ERR_TOO_LONG);
+    }
+
+    let (msg, payload) = take!(msg, length_or_id - MIN_FRAME_LEN)?;
+    let (msg, checksum) = nom::be_u16(msg)?;
+    check(msg, &body[..length_or_id as usize], checksum)?;
+
+    Ok((
+        msg,
+        Frame {
+            source,
+            cmd_type,
+            cmd_id: ack_or_id,
+            ack: false,
+            payload: payload.to_vec(),
+        },
+    ))
+}
+
+fn error<T>(input: &[u8], code: u32) -> IResult<&[u8], T> {
+    Err(nom::Err::Error(nom::Context::Code(
+        input,
+        nom::ErrorKind::Custom(code),
+    )))
+}
+
+// `body` is the frame after the preamble, up to the checksum
+fn check<'a>(rest: &'a [u8], body: &[u8], checksum: u16) -> IResult<&'a [u8], ()> {
+    let actual = codec::checksum_seeded(codec::PREAMBLE_SUM, body);
+    if actual != checksum {
+        debug!("Checksum mismatch: got {:04X}, computed {:04X}", checksum, actual);
+        return error(rest, ERR_CHECKSUM);
+    }
+    Ok((rest, ()))
+}
+
+fn mac(msg: &[u8]) -> IResult<&[u8], String> {
+    let (rest, mac) = take!(msg, 8)?;
+    match std::str::from_utf8(mac) {
+        Ok(mac) => Ok((rest, mac.to_owned())),
+        Err(_) => error(msg, ERR_MAC),
+    }
+}
+
+fn frame_error(e: &nom::Err<&[u8]>) -> ParseError {
+    match e {
+        nom::Err::Incomplete(_) => ParseError::Truncated { cmd_id: None },
+        nom::Err::Error(nom::Context::Code(input, nom::ErrorKind::Custom(code))) => {
+            match *code {
+                ERR_CHECKSUM => ParseError::Checksum,
+                ERR_TOO_SHORT => ParseError::LengthTooShort(input[0]),
+                ERR_TOO_LONG => ParseError::LengthTooLong(input[0]),
+                _ => ParseError::Malformed,
+            }
+        }
+        _ => ParseError::Malformed,
+    }
+}
+
+fn payload_error(frame: &Frame, e: nom::Err<&[u8]>) -> ParseError {
+    match e {
+        nom::Err::Incomplete(_) => ParseError::Truncated {
+            cmd_id: Some(frame.cmd_id),
+        },
+        nom::Err::Error(nom::Context::Code(input, nom::ErrorKind::Custom(ERR_MAC))) => {
+            ParseError::InvalidMac(input[..8].to_vec())
+        }
+        nom::Err::Error(nom::Context::Code(input, nom::ErrorKind::Tag)) => {
+            ParseError::UnexpectedRecord {
+                cmd_id: frame.cmd_id,
+                record: input[0],
+            }
+        }
+        _ => ParseError::Malformed,
+    }
+}
+
+// Checks that `frame` carries the payload of `cmd_id`
+fn expect(frame: &Frame, cmd_id: CommandId) -> Result<(), ParseError> {
+    if frame.cmd_id != u8::from(cmd_id) {
+        return Err(ParseError::UnknownCommand(frame.cmd_id));
+    }
+    if frame.ack {
+        return Err(ParseError::Ack(frame.cmd_id));
+    }
+    Ok(())
+}
+
+// Looks up a type byte, falling back to a guess for unknown ones
+fn sensor_type(raw: u8, mac: &str, payload: &[u8]) -> (SensorType, bool) {
+    match SensorType::from(raw) {
+        SensorType::Unknown(raw) => {
+            // Keep a sample around so the type can be supported properly
+            warn!(
+                "Unknown sensor type {:02X} from {}, payload: {:02X?}",
+                raw, mac, payload
+            );
+            match sensor::guess_sensor_type(raw) {
+                Some(guess) => (guess, true),
+                None => (SensorType::Unknown(raw), false),
+            }
+        }
+        known => (known, false),
+    }
+}
+
+// <timestamp: 8> <len> A2 <mac: 8> <type> <state> <counter: 2>
+fn sensor_event(payload: &[u8]) -> IResult<&[u8], SensorEvent> {
+    let (msg, timestamp) = nom::be_u64(payload)?;
+    let (msg, _len) = nom::be_u8(msg)?;
+    let (msg, _) = tag!(msg, &[0xA2][..])?;
+    let (msg, mac) = mac(msg)?;
+    let (msg, raw_type) = nom::be_u8(msg)?;
+    let (msg, state) = nom::be_u8(msg)?;
+    let (msg, counter) = nom::be_u16(msg)?;
+
+    let (sensor_type, type_guessed) = sensor_type(raw_type, &mac, payload);
+
+    Ok((
+        msg,
+        SensorEvent {
+            timestamp,
+            mac,
+            sensor_type,
+            type_guessed,
+            state,
+            counter,
+        },
+    ))
+}
+
+// <timestamp: 8> <tag> <record>, see AlarmRecord
+fn alarm_record(payload: &[u8]) -> IResult<&[u8], AlarmRecord> {
+    let (msg, timestamp) = nom::be_u64(payload)?;
+    match msg.first() {
+        Some(0xA2) => sensor_alarm(&msg[1..], timestamp, payload)
+            .map(|(msg, alarm)| (msg, AlarmRecord::Status(alarm))),
+        Some(0xAB) => sensor_join(&msg[1..], timestamp)
+            .map(|(msg, join)| (msg, AlarmRecord::Join(join))),
+        Some(_) => Err(nom::Err::Error(nom::Context::Code(msg, nom::ErrorKind::Tag))),
+        None => Err(nom::Err::Incomplete(nom::Needed::Size(1))),
+    }
+}
+
+// A2 <mac: 8> <type> ?? <battery> ?? ?? <state> <counter: 2> <lqi>
+fn sensor_alarm<'a>(
+    msg: &'a [u8],
+    timestamp: u64,
+    payload: &[u8],
+) -> IResult<&'a [u8], SensorAlarmPacket> {
+    let (msg, mac) = mac(msg)?;
+    let (msg, raw_type) = nom::be_u8(msg)?;
+    let (msg, _) = take!(msg, 1)?;
+    let (msg, battery) = nom::be_u8(msg)?;
+    let (msg, _) = take!(msg, 2)?;
+    let (msg, state) = nom::be_u8(msg)?;
+    let (msg, counter) = nom::be_u16(msg)?;
+    let (msg, lqi) = nom::be_u8(msg)?;
+
+    let (sensor_type, type_guessed) = sensor_type(raw_type, &mac, payload);
+
+    Ok((
+        msg,
+        SensorAlarmPacket {
+            timestamp,
+            mac,
+            sensor_type,
+            type_guessed,
+            state,
+            battery,
+            lqi,
+            counter,
+        },
+    ))
+}
+
+// AB <mac: 8> <type> <data>
+fn sensor_join(msg: &[u8], timestamp: u64) -> IResult<&[u8], SensorJoinPacket> {
+    let (msg, mac) = mac(msg)?;
+    let (msg, sensor_type) = nom::be_u8(msg)?;
+
+    Ok((
+        &msg[msg.len()..],
+        SensorJoinPacket {
+            timestamp,
+            mac,
+            sensor_type: SensorType::from(sensor_type),
+            data: msg.to_vec(),
+        },
+    ))
+}
+
+// ?? <mac: 8> <type> <version>
+fn sensor_scan(payload: &[u8]) -> IResult<&[u8], SensorScanPacket> {
+    let (msg, _) = take!(payload, 1)?;
+    let (msg, mac) = mac(msg)?;
+    let (msg, sensor_type) = nom::be_u8(msg)?;
+    let (msg, version) = nom::be_u8(msg)?;
+
+    Ok((
+        msg,
+        SensorScanPacket {
+            mac,
+            sensor_type: SensorType::from(sensor_type),
+            version,
+        },
+    ))
+}
+
+// Decodes an event log (0x35) frame. Fails with UnexpectedRecord for entries
+// other than sensor events.
+pub fn parse_sensor_event(frame: &Frame) -> Result<SensorEventPacket, ParseError> {
+    expect(frame, CommandId::EventLog)?;
+    sensor_event(&frame.payload)
+        .map(|(_, event)| SensorEventPacket { event })
+        .map_err(|e| payload_error(frame, e))
+}
+
+// Decodes the record in an alarm (0x19) frame. Fails with UnexpectedRecord
+// for tags other than A2 and AB.
+pub fn parse_alarm_record(frame: &Frame) -> Result<AlarmRecord, ParseError> {
+    expect(frame, CommandId::Alarm)?;
+    alarm_record(&frame.payload)
+        .map(|(_, record)| record)
+        .map_err(|e| payload_error(frame, e))
+}
+
+// Decodes an alarm (0x19) frame. Fails with UnexpectedRecord for records
+// other than sensor status reports.
+pub fn parse_sensor_alarm(frame: &Frame) -> Result<SensorAlarmPacket, ParseError> {
+    match parse_alarm_record(frame)? {
+        AlarmRecord::Status(alarm) => Ok(alarm),
+        AlarmRecord::Join(_) => Err(ParseError::UnexpectedRecord {
+            cmd_id: frame.cmd_id,
+            record: 0xAB,
+        }),
+    }
+}
+
+// Decodes a scan result (0x20) frame
+pub fn parse_sensor_scan(frame: &Frame) -> Result<SensorScanPacket, ParseError> {
+    expect(frame, CommandId::SensorScan)?;
+    sensor_scan(&frame.payload)
+        .map(|(_, scan)| scan)
+        .map_err(|e| payload_error(frame, e))
+}
+
+pub fn parse_payload(frame: &Frame) -> Result<PacketPayload, ParseError> {
+    match CommandId::try_from(frame.cmd_id) {
+        Ok(CommandId::SensorScan) => parse_sensor_scan(frame).map(PacketPayload::SensorScan),
+        Ok(CommandId::Alarm) => parse_alarm_record(frame).map(PacketPayload::from),
+        Ok(CommandId::EventLog) => parse_sensor_event(frame).map(PacketPayload::SensorEvent),
+        _ if frame.ack => Err(ParseError::Ack(frame.cmd_id)),
+        _ => {
+            debug!("Unknown {:02X} frame: {:02X?}", frame.cmd_id, frame.payload);
+            Ok(PacketPayload::Unknown {
+                id: frame.cmd_id,
+                payload: frame.payload.clone(),
+            })
+        }
+    }
+}
+
+// Decodes every frame in a standalone buffer. Frames that can't be decoded
+// are returned as errors and skipped.
+pub fn decode_all(data: &[u8]) -> Vec<Result<Frame, ParseError>> {
+    decode_all_raw(data)
+        .into_iter()
+        .map(|result| result.map(|(_, frame)| frame))
+        .collect()
+}
+
+// decode_all() along with the bytes each frame was decoded from, preamble
+// through checksum
+pub fn decode_all_raw(data: &[u8]) -> Vec<Result<(&[u8], Frame), ParseError>> {
+    let mut results = vec![];
+    let mut msg = data;
+    while !msg.is_empty() {
+        match parse_frame(msg) {
+            Ok((remaining, frame)) => {
+                // parse_frame() only succeeds past a preamble
+                let start = msg.windows(2).position(is_preamble).unwrap_or(0);
+                let end = msg.len() - remaining.len();
+                results.push(Ok((&msg[start..end], frame)));
+                msg = remaining;
+            }
+            Err(nom::Err::Error(nom::Context::Code(
+                _,
+                nom::ErrorKind::Custom(ERR_CHECKSUM),
+            ))) => {
+                results.push(Err(ParseError::Checksum));
+                match next_preamble(msg) {
+                    Some(next) => msg = next,
+                    None => break,
+                }
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                if msg.windows(2).any(is_preamble) {
+                    results.push(Err(ParseError::Truncated { cmd_id: None }));
+                }
+                break;
+            }
+            Err(e) => {
+                results.push(Err(frame_error(&e)));
+                match next_preamble(msg) {
+                    Some(next) => msg = next,
+                    None => break,
+                }
+            }
+        }
+    }
+    results
+}
+
+// Skips the preamble of the first frame in `msg` and returns the rest from
+// the preamble after it, if any
+fn next_preamble(msg: &[u8]) -> Option<&[u8]> {
+    let start = msg.windows(2).position(is_preamble).unwrap_or(0);
+    let rest = &msg[start + 1..];
+    rest.windows(2).position(is_preamble).map(|pos| &rest[pos..])
+}
+
+// Valid bytes of a HID report: <count> <data: count> <stale padding>
+pub fn report_data(report: &[u8]) -> &[u8] {
+    match report.split_first() {
+        Some((&count, data)) => &data[..std::cmp::min(count as usize, data.len())],
+        None => &[],
+    }
+}
+
+// Reassembles frames from consecutive HID reports. Reports are fed in as
+// they are read and complete frames taken out with next_frame(). A frame
+// that is cut off at the end of one report is kept until the rest of it
+// arrives, however many reports that takes.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    host_echoes: u64,
+    corrupt_frames: u64,
+}
+
+impl FrameDecoder {
+    // Adds bytes of the frame stream
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // Adds a report as read from the dongle. The first byte of a report is
+    // the number of valid bytes after it. The rest of the 64 bytes is left
+    // over from earlier reports and would otherwise be decoded again.
+    pub fn feed_report(&mut self, report: &[u8]) {
+        self.feed(report_data(report));
+    }
+
+    // Next complete frame from the bridge, if one has been fed in. Echoed
+    // host frames and corrupt frames are dropped along the way.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        loop {
+            match parse_frame(&self.buf) {
+                Ok((remaining, frame)) => {
+                    let consumed = self.buf.len() - remaining.len();
+                    self.buf.drain(..consumed);
+                    if frame.source == PacketSource::Host {
+                        self.host_echoes += 1;
+                        debug!("Dropping echoed host frame: {:?}", frame);
+                    } else {
+                        info!(
+                            "Found msg type: {:?}, cmd_id: {:?}, ack: {:?}",
+                            frame.cmd_type, frame.cmd_id, frame.ack
+                        );
+                        return Some(frame);
+                    }
+                }
+                Err(nom::Err::Error(nom::Context::Code(
+                    remaining,
+                    nom::ErrorKind::Custom(ERR_CHECKSUM),
+                ))) => {
+                    self.corrupt_frames += 1;
+                    let consumed = self.buf.len() - remaining.len();
+                    warn!("Dropping corrupt frame: {:02X?}", &self.buf[..consumed]);
+                    // A corrupt length byte can make the frame reach into
+                    // the next one, so carry on from the next preamble
+                    // rather than after the bytes it claimed
+                    self.discard_leading_garbage();
+                    self.resync();
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    self.discard_leading_garbage();
+                    if !self.buf.is_empty() {
+                        trace!("Holding {} byte(s) of a partial frame", self.buf.len());
+                    }
+                    return None;
+                }
+                Err(e) => {
+                    // Skip the preamble of the bad frame and carry on from
+                    // the next one, so frames after it aren't lost
+                    debug!("Bad frame: {}", frame_error(&e));
+                    self.resync();
+                }
+            }
+        }
+    }
+
+    // Feeds a report and returns all frames completed by it
+    pub fn decode(&mut self, report: &[u8]) -> Vec<Frame> {
+        self.feed_report(report);
+        let mut frames = vec![];
+        while let Some(frame) = self.next_frame() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    // Drops everything buffered, e.g. after losing sync with the dongle.
+    // The counters are kept.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    // Number of host frames echoed back by the dongle that were dropped
+    pub fn host_echoes(&self) -> u64 {
+        self.host_echoes
+    }
+
+    // Number of frames dropped because their checksum didn't match
+    pub fn corrupt_frames(&self) -> u64 {
+        self.corrupt_frames
+    }
+
+    // Drops the frame at the front of the buffer up to the next preamble
+    fn resync(&mut self) {
+        let next = self.buf[1..]
+            .windows(2)
+            .position(is_preamble)
+            .map(|pos| pos + 1)
+            .unwrap_or(self.buf.len());
+        debug!("Resyncing, dropping {:02X?}", &self.buf[..next]);
+        self.buf.drain(..next);
+    }
+
+    // Drops everything in front of the first preamble. If there is no
+    // preamble, only a trailing byte that may be the first half of one is
+    // kept.
+    fn discard_leading_garbage(&mut self) {
+        let start = self
+            .buf
+            .windows(2)
+            .position(is_preamble)
+            .unwrap_or_else(|| match self.buf.last() {
+                Some(&b) if b == BRIDGE_PREAMBLE[0] || b == HOST_PREAMBLE[0] => {
+                    self.buf.len() - 1
+                }
+                _ => self.buf.len(),
+            });
+        self.buf.drain(..start);
+    }
+}
@@ -0,0 +1,824 @@
+use bytes::{Bytes, BytesMut};
+use bytes::BufMut;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::codec;
+use crate::command::CommandId;
+use crate::sensor::SensorType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PacketSyncType {
+    Sync,
+    Async,
+}
+
+pub struct ReceivedPacket<T>
+    where T: Packet 
+{
+    pub lqi: u8,
+    pub packet_type: PacketType,
+    pub packet: T
+}
+
+impl<T> ReceivedPacket<T>
+    where T: Packet
+{
+    pub fn into_inner(self) -> T {
+        self.packet
+    }
+}
+
+pub enum PacketType {
+    GetEnr,
+    Auth,
+    GetMac,
+    GetKey,
+    Inquiry,
+    GetVer,
+    GetSensorCount,
+    SetRandom,
+    StartStopNetwork,
+    GetSensorList,
+    Event,
+    AddSensor,
+    Ack,
+}
+
+pub trait Packet {
+    fn get_packet_type(&self) -> PacketSyncType;
+    
+    fn get_packet_id(&self) -> u8;
+}
+
+pub trait Parseable {
+    fn from_bytes(data: Bytes) -> Self;
+}
+
+pub trait Packable {
+    fn to_bytes(&self) -> Bytes;
+}
+
+impl Packable for dyn Packet {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+// Challenge sent at the start of the handshake. The dongle answers with
+// 0x03 carrying the 16 byte ENR derived from it.
+#[derive(Debug)]
+pub struct EnrPacket {
+    random: [u8; 16],
+}
+impl Packet for EnrPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetEnr.into()
+    }
+}
+
+impl Packable for EnrPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(17);
+        buf.put_u8(self.get_packet_id());
+        buf.put_slice(&self.random);
+        buf.into()
+    }
+}
+impl EnrPacket {
+    pub fn create(random: [u8; 16]) -> EnrPacket {
+        EnrPacket { random }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthPacket {
+    completion: u8,
+}
+impl AuthPacket {
+    pub fn create_done() -> AuthPacket {
+        AuthPacket {
+            completion: 0xFF,
+        }
+    }
+    
+    pub fn create_blinking() -> AuthPacket {
+        AuthPacket {
+            completion: 0x00,
+        }
+    }
+}
+impl Packet for AuthPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::Auth.into()
+    }
+}
+
+impl Packable for AuthPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u8(self.completion);
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct GetMacPacket;
+impl Packet for GetMacPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetMac.into()
+    }
+}
+
+impl Packable for GetMacPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+// Answered with 0x07 carrying the dongle's key
+#[derive(Debug)]
+pub struct GetKeyPacket;
+impl Packet for GetKeyPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetKey.into()
+    }
+}
+
+impl Packable for GetKeyPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct InquiryPacket;
+impl Packet for InquiryPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::Inquiry.into()
+    }
+}
+
+impl Packable for InquiryPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct GetVerPacket;
+impl Packet for GetVerPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetVersion.into()
+    }
+}
+
+impl Packable for GetVerPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+// Any command, for probing ids that haven't been mapped yet. The dongle
+// answers known commands with <id + 1>.
+#[derive(Debug)]
+pub struct RawPacket {
+    packet_type: PacketSyncType,
+    id: u8,
+    payload: Vec<u8>,
+}
+impl Packet for RawPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        self.packet_type
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        self.id
+    }
+}
+
+impl Packable for RawPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1 + self.payload.len());
+        buf.put_u8(self.id);
+        buf.put_slice(&self.payload);
+        buf.into()
+    }
+}
+impl RawPacket {
+    pub fn create(packet_type: PacketSyncType, id: u8, payload: &[u8]) -> RawPacket {
+        RawPacket {
+            packet_type,
+            id,
+            payload: payload.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GetSensorCountPacket;
+impl Packet for GetSensorCountPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetSensorCount.into()
+    }
+}
+
+impl Packable for GetSensorCountPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct SetRandomPacket {
+    // Sensor the random value is for
+    mac: String,
+    data: [u8; 16],
+}
+impl Packet for SetRandomPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SetRandom.into()
+    }
+}
+
+impl Packable for SetRandomPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(25);
+        buf.put_u8(self.get_packet_id());
+        buf.put_slice(self.mac.as_bytes());
+        buf.put_slice(&self.data);
+        buf.into()
+    }
+}
+impl SetRandomPacket {
+    pub fn create(mac: &str, data: [u8; 16]) -> SetRandomPacket {
+        SetRandomPacket {
+            mac: mac.to_owned(),
+            data
+        } 
+    }
+}
+
+// Completes binding a sensor found while in join mode
+// payload: <mac: 8> FF 04
+#[derive(Debug)]
+pub struct VerifySensorPacket {
+    mac: String,
+}
+impl Packet for VerifySensorPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::VerifySensor.into()
+    }
+}
+
+impl Packable for VerifySensorPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(11);
+        buf.put_u8(self.get_packet_id());
+        buf.put_slice(self.mac.as_bytes());
+        buf.put_slice(&[0xFF, 0x04]);
+        buf.into()
+    }
+}
+impl VerifySensorPacket {
+    pub fn create(mac: &str) -> VerifySensorPacket {
+        VerifySensorPacket {
+            mac: mac.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StartStopNetworkPacket {
+    join_mode: bool,
+}
+impl Packet for StartStopNetworkPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::StartStopNetwork.into()
+    }
+}
+
+impl Packable for StartStopNetworkPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u8(if self.join_mode { 0x01 } else { 0x00 });
+        buf.into()
+    }
+}
+impl StartStopNetworkPacket {
+    pub fn create(join_mode: bool) -> StartStopNetworkPacket {
+        StartStopNetworkPacket {
+            join_mode
+        } 
+    }
+}
+
+#[derive(Debug)]
+pub struct GetSensorListPacket {
+    count: u8,
+}
+
+impl Packet for GetSensorListPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::GetSensorList.into()
+    }
+}
+
+impl Packable for GetSensorListPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u8(self.count);
+        buf.into()
+    }
+}
+
+impl GetSensorListPacket {
+    pub fn create(count: u8) -> GetSensorListPacket {
+        GetSensorListPacket {
+            count
+        } 
+    }
+}
+
+
+// 2019-06-24 22:20:25,984 TRACE [wyze] Read 63: [3E, 55, AA, 53, 19, 35, 0, 0, 0, 0, 0, 0, 0, 0, 0E, A2, 37, 37, 37, 42, 31, 39, 36, 32, 01, 01, 00, 51, 04, 5C, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 1, 0, 51, 3D, 4, EE]
+// 2019-06-24 22:20:31,836 TRACE [wyze] Read 63: [3E, 55, AA, 53, 19, 35, 0, 0, 0, 0, 0, 0, 0, 0, 0E, A2, 37, 37, 37, 42, 31, 39, 36, 32, 01, 00, 00, 52, 04, 5C, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorEvent {
+    // Milliseconds since the epoch, as reported by the dongle
+    pub timestamp: u64,
+    pub mac: String,
+    pub sensor_type: SensorType,
+    // Set when the type byte wasn't recognised and sensor_type is a guess
+    pub type_guessed: bool,
+    pub state: u8,
+    pub counter: u16,
+}
+
+// A decoded frame from the bridge
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketPayload {
+    SensorEvent(SensorEventPacket),
+    SensorAlarm(SensorAlarmPacket),
+    SensorJoin(SensorJoinPacket),
+    SensorScan(SensorScanPacket),
+    // Anything else the bridge sends unprompted, passed on as is so new
+    // messages can be looked into
+    Unknown { id: u8, payload: Vec<u8> },
+}
+
+impl PacketPayload {
+    // Frames the payload the way the bridge sends it, 55 AA through the
+    // checksum. A sensor type that was guessed is written as the guess, so
+    // it parses back with type_guessed unset.
+    pub fn pack(&self) -> Vec<u8> {
+        match self {
+            PacketPayload::SensorEvent(packet) => codec::encode_bridge(packet),
+            PacketPayload::SensorAlarm(alarm) => codec::encode_bridge(alarm),
+            PacketPayload::SensorJoin(join) => codec::encode_bridge(join),
+            PacketPayload::SensorScan(scan) => codec::encode_bridge(scan),
+            PacketPayload::Unknown { id, payload } => {
+                codec::encode_bridge(&RawPacket::create(PacketSyncType::Async, *id, payload))
+            }
+        }
+    }
+
+    // Mac of the sensor the payload is about, empty for unknown payloads
+    pub fn mac(&self) -> &str {
+        match self {
+            PacketPayload::SensorEvent(packet) => &packet.event.mac,
+            PacketPayload::SensorAlarm(alarm) => &alarm.mac,
+            PacketPayload::SensorJoin(join) => &join.mac,
+            PacketPayload::SensorScan(scan) => &scan.mac,
+            PacketPayload::Unknown { .. } => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorEventPacket {
+    // preamble, len, id:
+    // 55 AA 53 19 35
+    // payload:
+    // <timestamp: 8> <len> <entry: len>
+    // 00 00 00 00 00 00 00 00 0E A2 <37 37 37 42 31 39 36 32> <01> <01> <00 51>
+    //
+    // Entries tagged A2 are sensor events: mac (ASCII), device type, state
+    // and an event counter. Other tags log dongle activity and are not
+    // decoded.
+    // checksum:
+    // 04 5C
+    pub event: SensorEvent,
+}
+impl Packet for SensorEventPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::EventLog.into()
+    }
+}
+
+// The length byte in captured frames is one more than the entry after it
+impl Packable for SensorEventPacket {
+    fn to_bytes(&self) -> Bytes {
+        let event = &self.event;
+        let mut buf = BytesMut::with_capacity(23);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u64_be(event.timestamp);
+        buf.put_u8(14);
+        buf.put_u8(0xA2);
+        buf.put_slice(event.mac.as_bytes());
+        buf.put_u8(event.sensor_type.into());
+        buf.put_u8(event.state);
+        buf.put_u16_be(event.counter);
+        buf.into()
+    }
+}
+
+
+// 2019-06-24 22:20:31,928 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:20:32,016 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:20:32,103 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:21:24,164 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:21:24,251 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:21:24,338 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+// 2019-06-24 22:21:24,426 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorAlarmPacket {
+    // payload:
+    // <timestamp: 8> A2 <mac: 8> <type> ?? <battery> ?? ?? <state> <counter: 2> <lqi>
+    // 00 00 00 00 00 00 00 00 A2 <37 37 37 42 31 39 36 32> 01 1A 60 00 01 01 00 51 3D
+    //
+    // This is the A2 record of an alarm frame, see AlarmRecord.
+
+    // Milliseconds since the epoch, as reported by the dongle
+    pub timestamp: u64,
+    pub mac: String,
+    pub sensor_type: SensorType,
+    // Set when the type byte wasn't recognised and sensor_type is a guess
+    pub type_guessed: bool,
+    // Contact sensors: 1 = open, 0 = closed. Motion sensors: 1 = motion.
+    pub state: u8,
+    // Percent
+    pub battery: u8,
+    // Link quality of the received frame, added by the bridge after the
+    // sensor's record. The sensor's own view of the link (RSSI) isn't in
+    // any captured layout yet and gets a field of its own once found.
+    pub lqi: u8,
+    pub counter: u16,
+}
+impl Packet for SensorAlarmPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::Alarm.into()
+    }
+}
+
+// Bytes that aren't decoded are written as zero
+impl Packable for SensorAlarmPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(27);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u64_be(self.timestamp);
+        buf.put_u8(0xA2);
+        buf.put_slice(self.mac.as_bytes());
+        buf.put_u8(self.sensor_type.into());
+        buf.put_u8(0);
+        buf.put_u8(self.battery);
+        buf.put_u16_be(0);
+        buf.put_u8(self.state);
+        buf.put_u16_be(self.counter);
+        buf.put_u8(self.lqi);
+        buf.into()
+    }
+}
+
+// Records carried in alarm (0x19) payloads. The byte after the timestamp is
+// a tag that selects the layout of the rest of the payload:
+//
+// A2 <mac: 8> 01 1A 60 00 01 01 00 51 3D
+// AB <mac: 8> 02 01 05 03 05 03 07 05 00 07 05 04 00 40 00
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlarmRecord {
+    // A2: state report from a paired sensor
+    Status(SensorAlarmPacket),
+    // AB: seen while a motion sensor joined (see the lines above)
+    Join(SensorJoinPacket),
+}
+
+impl From<AlarmRecord> for PacketPayload {
+    fn from(record: AlarmRecord) -> PacketPayload {
+        match record {
+            AlarmRecord::Status(alarm) => PacketPayload::SensorAlarm(alarm),
+            AlarmRecord::Join(join) => PacketPayload::SensorJoin(join),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorJoinPacket {
+    // payload:
+    // <timestamp: 8> AB <mac: 8> <type> <data>
+    //
+    // The layout after the type byte isn't mapped yet, so it is kept as is
+    pub timestamp: u64,
+    pub mac: String,
+    pub sensor_type: SensorType,
+    pub data: Vec<u8>,
+}
+impl Packet for SensorJoinPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::Alarm.into()
+    }
+}
+
+impl Packable for SensorJoinPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(19 + self.data.len());
+        buf.put_u8(self.get_packet_id());
+        buf.put_u64_be(self.timestamp);
+        buf.put_u8(0xAB);
+        buf.put_slice(self.mac.as_bytes());
+        buf.put_u8(self.sensor_type.into());
+        buf.put_slice(&self.data);
+        buf.into()
+    }
+}
+
+// Sent while in join mode when a sensor in pairing mode is heard
+// payload: ?? <mac: 8> <type> <version>
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorScanPacket {
+    pub mac: String,
+    pub sensor_type: SensorType,
+    pub version: u8,
+}
+impl Packet for SensorScanPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SensorScan.into()
+    }
+}
+
+// The leading byte isn't decoded and is written as zero
+impl Packable for SensorScanPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(12);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u8(0);
+        buf.put_slice(self.mac.as_bytes());
+        buf.put_u8(self.sensor_type.into());
+        buf.put_u8(self.version);
+        buf.into()
+    }
+}
+
+// 2019-06-24 22:20:57,659 TRACE [wyze] Read 63: [7, 55, AA, 53, 3, 32, 1, 87, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
+#[derive(Debug)]
+pub struct SensorNotifySyncTimePacket {
+    // Stuff
+}
+impl Packet for SensorNotifySyncTimePacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SyncTime.into()
+    }
+}
+
+impl Packable for SensorNotifySyncTimePacket {
+    fn to_bytes(&self) -> Bytes {
+        // This is an incoming message
+        unimplemented!()
+    }
+}
+
+// Answer to 0x32. The dongle uses this time to stamp sensor events.
+// payload: <timestamp: 8> (milliseconds since the epoch, big endian)
+#[derive(Debug)]
+pub struct SyncTimeResponsePacket {
+    timestamp: u64,
+}
+impl Packet for SyncTimeResponsePacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SyncTimeResponse.into()
+    }
+}
+
+impl Packable for SyncTimeResponsePacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(9);
+        buf.put_u8(self.get_packet_id());
+        buf.put_u64_be(self.timestamp);
+        buf.into()
+    }
+}
+impl SyncTimeResponsePacket {
+    pub fn create(timestamp: u64) -> SyncTimeResponsePacket {
+        SyncTimeResponsePacket { timestamp }
+    }
+
+    pub fn now() -> SyncTimeResponsePacket {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        SyncTimeResponsePacket::create(
+            since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis()),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct AddSensorPacket {
+    // TODO: sensor MAC, type, version
+}
+impl Packet for AddSensorPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SensorScan.into()
+    }
+}
+
+impl Packable for AddSensorPacket {
+    fn to_bytes(&self) -> Bytes {
+        // This is an incoming message
+        unimplemented!()
+    }
+}
+
+#[derive(Debug)]
+pub struct DeleteSensorCommandPacket {
+    mac: String,
+}
+impl Packet for DeleteSensorCommandPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::DeleteSensor.into()
+    }
+}
+
+// payload: <mac: 8>
+impl Packable for DeleteSensorCommandPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(9);
+        buf.put_u8(self.get_packet_id());
+        buf.put_slice(self.mac.as_bytes());
+        buf.into()
+    }
+}
+impl DeleteSensorCommandPacket {
+    pub fn create(mac: &str) -> DeleteSensorCommandPacket {
+        DeleteSensorCommandPacket {
+            mac: mac.to_owned(),
+        }
+    }
+}
+
+// Firmware update commands. These switch the dongle into its bootloaders
+// for the CC1310 radio and the CH554 USB controller respectively. The image
+// transfer that follows is not known yet, so nothing sends these.
+#[derive(Debug)]
+pub struct UpdateCC1310Packet;
+impl Packet for UpdateCC1310Packet {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::UpdateCc1310.into()
+    }
+}
+
+impl Packable for UpdateCC1310Packet {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct SetCH554UpgradePacket;
+impl Packet for SetCH554UpgradePacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Sync
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::SetCh554Upgrade.into()
+    }
+}
+
+impl Packable for SetCH554UpgradePacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(1);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
+
+#[derive(Debug)]
+pub struct AckPacket {
+    for_packet_id: u8,
+}
+
+impl Packet for AckPacket {
+    fn get_packet_type(&self) -> PacketSyncType {
+        PacketSyncType::Async
+    }
+
+    fn get_packet_id(&self) -> u8 {
+        CommandId::Ack.into()
+    }
+}
+
+impl Packable for AckPacket {
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(2);
+        buf.put_u8(self.for_packet_id);
+        buf.put_u8(self.get_packet_id());
+        buf.into()
+    }
+}
@@ -0,0 +1,60 @@
+// Device type byte carried in sensor records
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorType {
+    Contact,
+    Motion,
+    Leak,
+    Unknown(u8),
+}
+
+impl From<u8> for SensorType {
+    fn from(raw: u8) -> SensorType {
+        match raw {
+            0x01 => SensorType::Contact,
+            0x02 => SensorType::Motion,
+            0x03 => SensorType::Leak,
+            _ => SensorType::Unknown(raw),
+        }
+    }
+}
+
+impl From<SensorType> for u8 {
+    fn from(sensor_type: SensorType) -> u8 {
+        match sensor_type {
+            SensorType::Contact => 0x01,
+            SensorType::Motion => 0x02,
+            SensorType::Leak => 0x03,
+            SensorType::Unknown(raw) => raw,
+        }
+    }
+}
+
+// Type bytes of second generation sensors. They report the same way as
+// the first generation ones, but aren't in the table above until a capture
+// confirms it.
+const CONTACT_V2: u8 = 0x0E;
+const MOTION_V2: u8 = 0x0F;
+
+// Guesses the type of a sensor whose type byte isn't in the table above.
+// Only the documented type bytes of newer sensors are guessed; anything
+// else returns None and stays Unknown.
+pub fn guess_sensor_type(raw: u8) -> Option<SensorType> {
+    match SensorType::from(raw) {
+        SensorType::Unknown(CONTACT_V2) => Some(SensorType::Contact),
+        SensorType::Unknown(MOTION_V2) => Some(SensorType::Motion),
+        SensorType::Unknown(_) => None,
+        known => Some(known),
+    }
+}
+
+impl SensorType {
+    // Stable lowercase name, used when events are sent to clients
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SensorType::Contact => "contact",
+            SensorType::Motion => "motion",
+            SensorType::Leak => "leak",
+            SensorType::Unknown(_) => "unknown",
+        }
+    }
+}
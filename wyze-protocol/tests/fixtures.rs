@@ -0,0 +1,306 @@
+// Frames captured from a real bridge, decoded by hand. Each is a whole frame,
+// 55 AA through the checksum.
+
+use wyze_protocol::magic::{
+    decode_all, parse_alarm_record, parse_payload, parse_sensor_alarm, report_data, MAX_FRAME_LEN,
+};
+use wyze_protocol::packet::*;
+use wyze_protocol::sensor::SensorType;
+use wyze_protocol::{codec, Frame, FrameDecoder, PacketSource, ParseError};
+
+// Alarm with an A2 record, from a contact sensor
+const ALARM_STATUS: &[u8] = &[
+    0x55, 0xAA, 0x53, 0x1D, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA2, 0x37,
+    0x37, 0x37, 0x42, 0x31, 0x39, 0x36, 0x32, 0x01, 0x1A, 0x60, 0x00, 0x01, 0x00, 0x00, 0x52,
+    0x44, 0x04, 0xF5,
+];
+
+// Alarm with an AB record, seen while a motion sensor joined
+const ALARM_JOIN: &[u8] = &[
+    0x55, 0xAA, 0x53, 0x23, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xAB, 0x37,
+    0x37, 0x37, 0x41, 0x43, 0x32, 0x36, 0x30, 0x02, 0x01, 0x05, 0x03, 0x05, 0x03, 0x07, 0x05,
+    0x00, 0x07, 0x05, 0x04, 0x00, 0x40, 0x00, 0x04, 0x69,
+];
+
+fn frame(raw: &[u8]) -> Frame {
+    let mut decoded = decode_all(raw);
+    assert_eq!(decoded.len(), 1);
+    decoded.remove(0).unwrap()
+}
+
+#[test]
+fn alarm_status_record() {
+    let expected = SensorAlarmPacket {
+        timestamp: 0,
+        mac: "777B1962".to_string(),
+        sensor_type: SensorType::Contact,
+        type_guessed: false,
+        state: 0,
+        battery: 0x60,
+        lqi: 0x44,
+        counter: 0x0052,
+    };
+    let frame = frame(ALARM_STATUS);
+    assert_eq!(
+        parse_alarm_record(&frame),
+        Ok(AlarmRecord::Status(expected.clone()))
+    );
+    assert_eq!(parse_sensor_alarm(&frame), Ok(expected.clone()));
+    assert_eq!(parse_payload(&frame), Ok(PacketPayload::SensorAlarm(expected)));
+}
+
+#[test]
+fn alarm_join_record() {
+    let expected = SensorJoinPacket {
+        timestamp: 0,
+        mac: "777AC260".to_string(),
+        sensor_type: SensorType::Motion,
+        data: vec![
+            0x01, 0x05, 0x03, 0x05, 0x03, 0x07, 0x05, 0x00, 0x07, 0x05, 0x04, 0x00, 0x40, 0x00,
+        ],
+    };
+    let frame = frame(ALARM_JOIN);
+    assert_eq!(
+        parse_alarm_record(&frame),
+        Ok(AlarmRecord::Join(expected.clone()))
+    );
+    assert_eq!(
+        parse_sensor_alarm(&frame),
+        Err(ParseError::UnexpectedRecord {
+            cmd_id: 0x19,
+            record: 0xAB
+        })
+    );
+    assert_eq!(
+        parse_payload(&frame),
+        Ok(PacketPayload::SensorJoin(expected.clone()))
+    );
+    assert_eq!(PacketPayload::SensorJoin(expected).pack(), ALARM_JOIN);
+}
+
+// Rewrites the checksum of an edited frame
+fn fix_checksum(raw: &mut [u8]) {
+    let len = raw.len();
+    let checksum = codec::checksum(&raw[..len - 2]);
+    raw[len - 2] = (checksum >> 8) as u8;
+    raw[len - 1] = checksum as u8;
+}
+
+#[test]
+fn alarm_unknown_record() {
+    let mut raw = ALARM_STATUS.to_vec();
+    raw[13] = 0xA5;
+    fix_checksum(&mut raw);
+
+    assert_eq!(
+        parse_payload(&frame(&raw)),
+        Err(ParseError::UnexpectedRecord {
+            cmd_id: 0x19,
+            record: 0xA5
+        })
+    );
+}
+
+#[test]
+fn captured_checksums_verify() {
+    assert_eq!(codec::verify(ALARM_STATUS), Ok(()));
+    assert_eq!(codec::verify(ALARM_JOIN), Ok(()));
+}
+
+#[test]
+fn bad_checksum_is_rejected() {
+    // A flipped bit anywhere after the preamble, checksum included
+    for i in 2..ALARM_STATUS.len() {
+        if i == 3 {
+            // A different length is a different frame
+            continue;
+        }
+        let mut raw = ALARM_STATUS.to_vec();
+        raw[i] ^= 0x10;
+        let decoded: Vec<_> = decode_all(&raw).into_iter().map(Result::err).collect();
+        assert_eq!(decoded, vec![Some(ParseError::Checksum)], "bit flipped at {}", i);
+    }
+
+    let mut raw = ALARM_STATUS.to_vec();
+    raw[24] ^= 0x01;
+    let mut decoder = FrameDecoder::default();
+    decoder.feed(&raw);
+    decoder.feed(ALARM_JOIN);
+    let frame = decoder.next_frame().unwrap();
+    assert_eq!(frame.cmd_id, 0x19);
+    assert_eq!(frame.payload, ALARM_JOIN[5..ALARM_JOIN.len() - 2].to_vec());
+    assert_eq!(decoder.corrupt_frames(), 1);
+}
+
+// Frames taken out of `stream` by a decoder, by command id
+fn decoded_ids(stream: &[u8]) -> Vec<u8> {
+    let mut decoder = FrameDecoder::default();
+    decoder.feed(stream);
+    std::iter::from_fn(|| decoder.next_frame())
+        .map(|frame| frame.cmd_id)
+        .collect()
+}
+
+#[test]
+fn resyncs_after_malformed_frame() {
+    // Preamble, then a type byte that is neither 43 nor 53
+    let mut stream = vec![0x55, 0xAA, 0x99, 0x1D, 0x19];
+    stream.extend_from_slice(ALARM_STATUS);
+    // Declares more bytes than any frame, and would swallow the next one
+    stream.extend_from_slice(&[0x55, 0xAA, 0x53, 0xF0, 0x19]);
+    stream.extend_from_slice(ALARM_JOIN);
+
+    assert_eq!(decoded_ids(&stream), vec![0x19, 0x19]);
+
+    let decoded: Vec<_> = decode_all(&stream).into_iter().map(|r| r.is_ok()).collect();
+    assert_eq!(decoded, vec![false, true, false, true]);
+}
+
+#[test]
+fn resyncs_after_truncated_frame() {
+    // A frame cut off by a lost report, followed by whole ones. The cut
+    // frame's length reaches into the next, so its checksum fails, and the
+    // next frame is found at its preamble.
+    let mut stream = ALARM_JOIN[..20].to_vec();
+    stream.extend_from_slice(ALARM_STATUS);
+    stream.extend_from_slice(ALARM_JOIN);
+
+    assert_eq!(decoded_ids(&stream), vec![0x19, 0x19]);
+
+    let decoded: Vec<_> = decode_all(&stream).into_iter().map(|r| r.is_ok()).collect();
+    assert_eq!(decoded, vec![false, true, true]);
+}
+
+// A HID report as read from the dongle: <count> <data> <padding to 64>
+fn report(data: &[u8], padding: &[u8]) -> Vec<u8> {
+    let mut report = vec![data.len() as u8];
+    report.extend_from_slice(data);
+    report.extend_from_slice(padding);
+    report.resize(64, 0);
+    report
+}
+
+#[test]
+fn frame_spanning_reports() {
+    // The join frame is cut after its mac, and the status frame follows it
+    // in the second report
+    let (head, tail) = ALARM_JOIN.split_at(22);
+    let mut second = tail.to_vec();
+    second.extend_from_slice(ALARM_STATUS);
+
+    let mut decoder = FrameDecoder::default();
+    assert!(decoder.decode(&report(head, &[])).is_empty());
+    let frames = decoder.decode(&report(&second, &[]));
+
+    let payloads: Vec<_> = frames.iter().map(parse_payload).collect();
+    assert_eq!(
+        payloads,
+        vec![parse_payload(&frame(ALARM_JOIN)), parse_payload(&frame(ALARM_STATUS))]
+    );
+    assert!(payloads.iter().all(Result::is_ok));
+    assert_eq!(decoder.corrupt_frames(), 0);
+}
+
+#[test]
+fn report_padding_is_ignored() {
+    // The dongle doesn't clear its buffer between reports, so the bytes
+    // past the count are what was left of an earlier one
+    let ack = [0x55, 0xAA, 0x53, 0x19, 0xFF, 0x02, 0x6A];
+    let stale = report(&ack, ALARM_STATUS);
+    assert_eq!(report_data(&stale), &ack[..]);
+
+    let mut decoder = FrameDecoder::default();
+    let frames = decoder.decode(&stale);
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].ack);
+    assert_eq!(frames[0].cmd_id, 0x19);
+
+    // Nothing held over from the padding either
+    let frames = decoder.decode(&report(ALARM_JOIN, &[]));
+    assert_eq!(frames.len(), 1);
+    assert_eq!(parse_payload(&frames[0]), parse_payload(&frame(ALARM_JOIN)));
+}
+
+#[test]
+fn report_count_past_the_end() {
+    // A count larger than the report is cut at the report
+    let mut short = vec![0xFF];
+    short.extend_from_slice(ALARM_STATUS);
+    assert_eq!(report_data(&short), ALARM_STATUS);
+    assert_eq!(report_data(&[]), &[] as &[u8]);
+    assert_eq!(report_data(&[0x00, 0x55, 0xAA]), &[] as &[u8]);
+}
+
+// 55 AA 53 <len> 19 <payload: len - 3> with a valid checksum, whatever the
+// payload length
+fn with_length(len: u8, payload: usize) -> Vec<u8> {
+    let mut raw = vec![0x55, 0xAA, 0x53, len, 0x19];
+    raw.resize(raw.len() + payload, 0);
+    raw.extend_from_slice(&[0, 0]);
+    fix_checksum(&mut raw);
+    raw
+}
+
+fn decode_one(raw: &[u8]) -> Result<usize, ParseError> {
+    let mut decoded = decode_all(raw);
+    assert_eq!(decoded.len(), 1);
+    decoded.remove(0).map(|frame| frame.payload.len())
+}
+
+#[test]
+fn length_guards() {
+    assert_eq!(decode_one(&with_length(3, 0)), Ok(0));
+    assert_eq!(decode_one(&with_length(2, 0)), Err(ParseError::LengthTooShort(2)));
+    assert_eq!(decode_one(&with_length(0, 0)), Err(ParseError::LengthTooShort(0)));
+
+    let longest = MAX_FRAME_LEN as usize - 3;
+    assert_eq!(decode_one(&with_length(MAX_FRAME_LEN, longest)), Ok(longest));
+    assert_eq!(
+        decode_one(&with_length(MAX_FRAME_LEN + 1, longest + 1)),
+        Err(ParseError::LengthTooLong(MAX_FRAME_LEN + 1))
+    );
+
+    // The captured frames are well inside the limits
+    assert_eq!(decode_one(ALARM_JOIN), Ok(ALARM_JOIN.len() - 7));
+}
+
+#[test]
+fn too_long_length_doesnt_stall_the_decoder() {
+    // Without the guard the decoder would wait for 0xF0 bytes before
+    // giving up on this frame, holding up the status frame behind it
+    let mut raw = ALARM_STATUS.to_vec();
+    raw[3] = 0xF0;
+    let mut decoder = FrameDecoder::default();
+    let mut frames = decoder.decode(&report(&raw[..5], &[]));
+    frames.extend(decoder.decode(&report(ALARM_STATUS, &[])));
+
+    assert_eq!(frames.len(), 1);
+    assert_eq!(parse_payload(&frames[0]), parse_payload(&frame(ALARM_STATUS)));
+}
+
+#[test]
+fn frames_carry_their_source() {
+    // The host's own inquiry, echoed back by the dongle, then the bridge's
+    // ack of it and an alarm
+    let echo = codec::encode(&InquiryPacket);
+    let ack = [0x55, 0xAA, 0x53, 0x27, 0xFF, 0x02, 0x78];
+    let mut stream = echo;
+    stream.extend_from_slice(&ack);
+    stream.extend_from_slice(ALARM_STATUS);
+
+    let sources: Vec<_> = decode_all(&stream)
+        .into_iter()
+        .map(|frame| frame.unwrap().source)
+        .collect();
+    assert_eq!(
+        sources,
+        vec![PacketSource::Host, PacketSource::Bridge, PacketSource::Bridge]
+    );
+
+    // The decoder only passes on the bridge's frames
+    let mut decoder = FrameDecoder::default();
+    let frames = decoder.decode(&report(&stream, &[]));
+    assert_eq!(frames.len(), 2);
+    assert!(frames.iter().all(|frame| frame.source == PacketSource::Bridge));
+    assert_eq!(decoder.host_echoes(), 1);
+}
@@ -0,0 +1,294 @@
+// Everything that can be packed has to parse back to what was packed, with
+// the framing and checksum intact.
+
+use proptest::prelude::*;
+
+use wyze_protocol::codec;
+use wyze_protocol::magic::{decode_all, parse_payload, MAX_FRAME_LEN};
+use wyze_protocol::packet::*;
+use wyze_protocol::sensor::SensorType;
+use wyze_protocol::{FrameDecoder, PacketSource};
+
+// Sensor macs are 8 ASCII characters, e.g. "777B1962"
+fn mac() -> impl Strategy<Value = String> {
+    "[0-9A-F]{8}"
+}
+
+// The type bytes guess_sensor_type() recognises are left out, they parse
+// back as the guess
+fn sensor_type() -> impl Strategy<Value = SensorType> {
+    prop_oneof![
+        Just(SensorType::Contact),
+        Just(SensorType::Motion),
+        Just(SensorType::Leak),
+        any::<u8>()
+            .prop_filter("known type", |raw| ![0x01, 0x02, 0x03, 0x0E, 0x0F].contains(raw))
+            .prop_map(SensorType::Unknown),
+    ]
+}
+
+fn sensor_event() -> impl Strategy<Value = PacketPayload> {
+    (any::<u64>(), mac(), sensor_type(), any::<u8>(), any::<u16>()).prop_map(
+        |(timestamp, mac, sensor_type, state, counter)| {
+            PacketPayload::SensorEvent(SensorEventPacket {
+                event: SensorEvent {
+                    timestamp,
+                    mac,
+                    sensor_type,
+                    type_guessed: false,
+                    state,
+                    counter,
+                },
+            })
+        },
+    )
+}
+
+fn sensor_alarm() -> impl Strategy<Value = PacketPayload> {
+    (
+        any::<u64>(),
+        mac(),
+        sensor_type(),
+        any::<u8>(),
+        any::<u8>(),
+        any::<u8>(),
+        any::<u16>(),
+    )
+        .prop_map(|(timestamp, mac, sensor_type, state, battery, lqi, counter)| {
+            PacketPayload::SensorAlarm(SensorAlarmPacket {
+                timestamp,
+                mac,
+                sensor_type,
+                type_guessed: false,
+                state,
+                battery,
+                lqi,
+                counter,
+            })
+        })
+}
+
+fn sensor_join() -> impl Strategy<Value = PacketPayload> {
+    (
+        any::<u64>(),
+        mac(),
+        sensor_type(),
+        prop::collection::vec(any::<u8>(), 0..16),
+    )
+        .prop_map(|(timestamp, mac, sensor_type, data)| {
+            PacketPayload::SensorJoin(SensorJoinPacket {
+                timestamp,
+                mac,
+                sensor_type,
+                data,
+            })
+        })
+}
+
+fn sensor_scan() -> impl Strategy<Value = PacketPayload> {
+    (mac(), sensor_type(), any::<u8>()).prop_map(|(mac, sensor_type, version)| {
+        PacketPayload::SensorScan(SensorScanPacket {
+            mac,
+            sensor_type,
+            version,
+        })
+    })
+}
+
+// Any id without a decoder. FF is left out, in that position it marks an ack.
+fn unknown() -> impl Strategy<Value = PacketPayload> {
+    (
+        any::<u8>().prop_filter("decoded id", |id| ![0x19, 0x20, 0x35, 0xFF].contains(id)),
+        prop::collection::vec(any::<u8>(), 0..MAX_FRAME_LEN as usize - 3),
+    )
+        .prop_map(|(id, payload)| PacketPayload::Unknown { id, payload })
+}
+
+fn payload() -> impl Strategy<Value = PacketPayload> {
+    prop_oneof![sensor_event(), sensor_alarm(), sensor_join(), sensor_scan(), unknown()]
+}
+
+// Host packets go through encode(), and parse back as host frames with the
+// packet's id and payload
+fn check_host_packet<P>(packet: P) -> Result<(), TestCaseError>
+    where P: Packet + Packable
+{
+    let frame = codec::encode(&packet);
+    prop_assert!(codec::verify(&frame).is_ok());
+
+    let decoded = decode_all(&frame);
+    prop_assert_eq!(decoded.len(), 1);
+    let parsed = decoded[0].as_ref().unwrap();
+    prop_assert_eq!(parsed.source, PacketSource::Host);
+    prop_assert_eq!(parsed.cmd_id, packet.get_packet_id());
+    prop_assert_eq!(&parsed.payload[..], &packet.to_bytes()[1..]);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn payloads_round_trip(payload in payload()) {
+        let frame = payload.pack();
+        prop_assert!(codec::verify(&frame).is_ok());
+
+        let decoded = decode_all(&frame);
+        prop_assert_eq!(decoded.len(), 1);
+        let parsed = decoded[0].as_ref().unwrap();
+        prop_assert_eq!(parsed.source, PacketSource::Bridge);
+        prop_assert_eq!(parse_payload(parsed), Ok(payload));
+    }
+
+    // Back to back frames, cut at an arbitrary point as a HID read might
+    #[test]
+    fn payloads_round_trip_through_decoder(
+        payloads in prop::collection::vec(payload(), 1..8),
+        cut in any::<prop::sample::Index>(),
+    ) {
+        let stream: Vec<u8> = payloads.iter().flat_map(PacketPayload::pack).collect();
+        let (first, second) = stream.split_at(cut.index(stream.len()));
+
+        let mut decoder = FrameDecoder::default();
+        let mut decoded = vec![];
+        for part in &[first, second] {
+            decoder.feed(part);
+            while let Some(frame) = decoder.next_frame() {
+                decoded.push(parse_payload(&frame));
+            }
+        }
+        prop_assert_eq!(decoded, payloads.into_iter().map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn delete_round_trips(mac in mac()) {
+        check_host_packet(DeleteSensorCommandPacket::create(&mac))?;
+    }
+
+    #[test]
+    fn verify_round_trips(mac in mac()) {
+        check_host_packet(VerifySensorPacket::create(&mac))?;
+    }
+
+    #[test]
+    fn set_random_round_trips(mac in mac(), random in any::<u64>()) {
+        let mut data = [0; 16];
+        data[..8].copy_from_slice(&random.to_be_bytes());
+        check_host_packet(SetRandomPacket::create(&mac, data))?;
+    }
+
+    #[test]
+    fn sync_time_round_trips(timestamp in any::<u64>()) {
+        check_host_packet(SyncTimeResponsePacket::create(timestamp))?;
+    }
+
+    #[test]
+    fn join_mode_round_trips(join_mode in any::<bool>()) {
+        check_host_packet(StartStopNetworkPacket::create(join_mode))?;
+    }
+}
+
+// Cuts a frame stream into HID reports: <count> <up to 63 bytes> <padding>.
+// The padding is 0x55 so a decoder that reads past the count sees preambles.
+fn reports(stream: &[u8], chunk: usize) -> Vec<Vec<u8>> {
+    stream
+        .chunks(chunk)
+        .map(|data| {
+            let mut report = vec![data.len() as u8];
+            report.extend_from_slice(data);
+            report.resize(64, 0x55);
+            report
+        })
+        .collect()
+}
+
+// Bytes between frames, without anything that could start a preamble
+fn garbage() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(
+        any::<u8>().prop_filter("preamble byte", |b| *b != 0x55 && *b != 0xAA),
+        0..12,
+    )
+}
+
+proptest! {
+    #[test]
+    fn payloads_round_trip_across_reports(
+        payloads in prop::collection::vec(payload(), 1..8),
+        chunk in 1usize..64,
+    ) {
+        let stream: Vec<u8> = payloads.iter().flat_map(PacketPayload::pack).collect();
+
+        let mut decoder = FrameDecoder::default();
+        let mut decoded = vec![];
+        for report in reports(&stream, chunk) {
+            decoded.extend(decoder.decode(&report).iter().map(parse_payload));
+        }
+        prop_assert_eq!(decoded, payloads.into_iter().map(Ok).collect::<Vec<_>>());
+        prop_assert_eq!(decoder.corrupt_frames(), 0);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage(
+        framed in prop::collection::vec((garbage(), payload()), 1..8),
+        trailing in garbage(),
+    ) {
+        let mut stream = vec![];
+        for (garbage, payload) in &framed {
+            stream.extend_from_slice(garbage);
+            stream.extend(payload.pack());
+        }
+        stream.extend(trailing);
+
+        let mut decoder = FrameDecoder::default();
+        let mut decoded = vec![];
+        for report in reports(&stream, 63) {
+            decoded.extend(decoder.decode(&report).iter().map(parse_payload));
+        }
+        let payloads: Vec<_> = framed.into_iter().map(|(_, payload)| Ok(payload)).collect();
+        prop_assert_eq!(decoded, payloads);
+    }
+}
+
+#[test]
+fn frame_split_over_three_reports() {
+    let alarm = PacketPayload::SensorAlarm(SensorAlarmPacket {
+        timestamp: 1_561_414_857_659,
+        mac: "777B1962".to_string(),
+        sensor_type: SensorType::Contact,
+        type_guessed: false,
+        state: 1,
+        battery: 0x60,
+        lqi: 0x44,
+        counter: 0x52,
+    });
+    let frame = alarm.pack();
+
+    let mut decoder = FrameDecoder::default();
+    let reports = reports(&frame, 12);
+    assert_eq!(reports.len(), 3);
+    assert!(decoder.decode(&reports[0]).is_empty());
+    assert!(decoder.decode(&reports[1]).is_empty());
+    let frames = decoder.decode(&reports[2]);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(parse_payload(&frames[0]), Ok(alarm));
+}
+
+#[test]
+fn corrupt_frame_is_skipped() {
+    let scan = |version| {
+        PacketPayload::SensorScan(SensorScanPacket {
+            mac: "777B1962".to_string(),
+            sensor_type: SensorType::Contact,
+            version,
+        })
+    };
+    let mut corrupt = scan(1).pack();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0x01;
+    let mut stream = vec![0x00, 0x13, 0x37];
+    stream.extend(corrupt);
+    stream.extend(scan(2).pack());
+
+    let mut decoder = FrameDecoder::default();
+    let frames = decoder.decode(&reports(&stream, 63)[0]);
+    assert_eq!(frames.iter().map(parse_payload).collect::<Vec<_>>(), vec![Ok(scan(2))]);
+    assert_eq!(decoder.corrupt_frames(), 1);
+}
@@ -0,0 +1,58 @@
+// Sensor types from type bytes, and guesses for the ones that aren't known
+
+use wyze_protocol::magic::{decode_all, parse_payload};
+use wyze_protocol::packet::*;
+use wyze_protocol::sensor::{guess_sensor_type, SensorType};
+
+#[test]
+fn known_types_arent_guesses() {
+    assert_eq!(guess_sensor_type(0x01), Some(SensorType::Contact));
+    assert_eq!(guess_sensor_type(0x02), Some(SensorType::Motion));
+    assert_eq!(guess_sensor_type(0x03), Some(SensorType::Leak));
+}
+
+#[test]
+fn second_generation_types_are_guessed() {
+    assert_eq!(guess_sensor_type(0x0E), Some(SensorType::Contact));
+    assert_eq!(guess_sensor_type(0x0F), Some(SensorType::Motion));
+}
+
+#[test]
+fn other_types_arent_guessed() {
+    for raw in (0x00..=0xFF).filter(|raw| ![0x01, 0x02, 0x03, 0x0E, 0x0F].contains(raw)) {
+        assert_eq!(guess_sensor_type(raw), None, "type {:02X}", raw);
+    }
+}
+
+// An event from a sensor with type byte `raw` and a 0/1 state, as decoded
+fn decoded_event(raw: u8, state: u8) -> SensorEvent {
+    let payload = PacketPayload::SensorEvent(SensorEventPacket {
+        event: SensorEvent {
+            timestamp: 0,
+            mac: "777B1962".to_string(),
+            sensor_type: SensorType::Unknown(raw),
+            type_guessed: false,
+            state,
+            counter: 1,
+        },
+    });
+    let frame = decode_all(&payload.pack()).remove(0).unwrap();
+    match parse_payload(&frame) {
+        Ok(PacketPayload::SensorEvent(packet)) => packet.event,
+        other => panic!("not an event: {:?}", other),
+    }
+}
+
+#[test]
+fn guess_is_flagged_on_events() {
+    let event = decoded_event(0x0E, 1);
+    assert_eq!(event.sensor_type, SensorType::Contact);
+    assert!(event.type_guessed);
+}
+
+#[test]
+fn binary_state_alone_isnt_a_contact_sensor() {
+    let event = decoded_event(0x42, 1);
+    assert_eq!(event.sensor_type, SensorType::Unknown(0x42));
+    assert!(!event.type_guessed);
+}
@@ -0,0 +1,385 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::codec::hex;
+use crate::magic;
+use crate::packet::PacketPayload;
+use crate::PacketSource;
+use crate::sink::Sink;
+use crate::{ReinitRequest, ShutdownReason};
+
+// Clients connect to this stream socket to receive events
+pub const CLIENT_SOCKET: &str = "/tmp/wyze.client";
+
+// A client that can't take a line within this long is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// How long a reinit request waits for the handshake to be redone. Each step
+// can take a few seconds when the dongle doesn't answer.
+const REINIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Messages sent to clients, one JSON object per line:
+//
+// {"event":"sensor_event","timestamp":1561414857659,"mac":"777B1962","sensor_type":"contact","state":1,"counter":81}
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientMessage<'a> {
+    SensorEvent {
+        timestamp: u64,
+        mac: &'a str,
+        sensor_type: &'static str,
+        state: u8,
+        counter: u16,
+    },
+    Alarm {
+        timestamp: u64,
+        mac: &'a str,
+        sensor_type: &'static str,
+        state: u8,
+        battery: u8,
+        lqi: u8,
+        counter: u16,
+    },
+    // Sent by a sensor as it joins, see AlarmRecord
+    Join {
+        timestamp: u64,
+        mac: &'a str,
+        sensor_type: &'static str,
+        data: String,
+    },
+    Scan {
+        mac: &'a str,
+        sensor_type: &'static str,
+        version: u8,
+    },
+    // A frame with an id that isn't decoded, payload in hex
+    Unknown {
+        id: u8,
+        payload: String,
+    },
+    // Last message before the daemon goes offline
+    Shutdown {
+        reason: &'static str,
+        detail: String,
+    },
+}
+
+impl<'a> From<&'a ShutdownReason> for ClientMessage<'a> {
+    fn from(reason: &'a ShutdownReason) -> ClientMessage<'a> {
+        let detail = match reason {
+            ShutdownReason::DeviceLost(e) => format!("{:?}", e),
+            ShutdownReason::Panic(message) => message.clone(),
+            ShutdownReason::Signal(signal) => format!("signal {}", signal),
+        };
+        ClientMessage::Shutdown {
+            reason: reason.name(),
+            detail,
+        }
+    }
+}
+
+impl<'a> From<&'a PacketPayload> for ClientMessage<'a> {
+    fn from(payload: &'a PacketPayload) -> ClientMessage<'a> {
+        match payload {
+            PacketPayload::SensorEvent(packet) => ClientMessage::SensorEvent {
+                timestamp: packet.event.timestamp,
+                mac: &packet.event.mac,
+                sensor_type: packet.event.sensor_type.name(),
+                state: packet.event.state,
+                counter: packet.event.counter,
+            },
+            PacketPayload::SensorAlarm(alarm) => ClientMessage::Alarm {
+                timestamp: alarm.timestamp,
+                mac: &alarm.mac,
+                sensor_type: alarm.sensor_type.name(),
+                state: alarm.state,
+                battery: alarm.battery,
+                lqi: alarm.lqi,
+                counter: alarm.counter,
+            },
+            PacketPayload::SensorJoin(join) => ClientMessage::Join {
+                timestamp: join.timestamp,
+                mac: &join.mac,
+                sensor_type: join.sensor_type.name(),
+                data: hex(&join.data, ""),
+            },
+            PacketPayload::SensorScan(scan) => ClientMessage::Scan {
+                mac: &scan.mac,
+                sensor_type: scan.sensor_type.name(),
+                version: scan.version,
+            },
+            PacketPayload::Unknown { id, payload } => ClientMessage::Unknown {
+                id: *id,
+                payload: hex(payload, ""),
+            },
+        }
+    }
+}
+
+// Requests clients can send, one JSON object per line. Each is answered
+// with one line on the same connection, between event lines.
+//
+// {"method":"decode","hex":"55AA531CFF026D"}
+// {"method":"reinit"}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    // Decodes frames given as hex, with or without spaces
+    Decode { hex: String },
+    // Redoes the init handshake with the bridge, without a USB reset
+    Reinit,
+}
+
+#[derive(Debug, Serialize)]
+struct ReinitStep {
+    name: &'static str,
+    ok: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DecodedFrame<'a> {
+    // "bridge" (55 AA) or "host" (AA 55)
+    source: &'static str,
+    cmd_type: u8,
+    cmd_id: u8,
+    ack: bool,
+    payload: String,
+    // Same as the event that would be sent for this frame, if any
+    decoded: Option<ClientMessage<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Response<'a> {
+    Decode {
+        frames: Vec<DecodedFrame<'a>>,
+        errors: Vec<String>,
+    },
+    Reinit {
+        steps: Vec<ReinitStep>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Answers one request line
+fn handle_request(line: &str, reinit: &Sender<ReinitRequest>) -> String {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return encode_response(&Response::Error { error: e.to_string() }),
+    };
+
+    match request {
+        Request::Decode { hex: input } => {
+            let data = match parse_hex(&input) {
+                Some(data) => data,
+                None => {
+                    return encode_response(&Response::Error {
+                        error: "invalid hex".to_string(),
+                    })
+                }
+            };
+
+            let mut frames = vec![];
+            let mut errors = vec![];
+            for result in magic::decode_all(&data) {
+                match result {
+                    Ok(frame) => {
+                        let payload = magic::parse_payload(&frame);
+                        frames.push((frame, payload));
+                    }
+                    Err(e) => errors.push(e.to_string()),
+                }
+            }
+
+            let frames = frames
+                .iter()
+                .map(|(frame, payload)| DecodedFrame {
+                    source: match frame.source {
+                        PacketSource::Host => "host",
+                        PacketSource::Bridge => "bridge",
+                    },
+                    cmd_type: frame.cmd_type,
+                    cmd_id: frame.cmd_id,
+                    ack: frame.ack,
+                    payload: hex(&frame.payload, ""),
+                    decoded: payload.as_ref().ok().map(ClientMessage::from),
+                })
+                .collect();
+            encode_response(&Response::Decode { frames, errors })
+        }
+        Request::Reinit => {
+            let (reply, steps) = mpsc::sync_channel(1);
+            if reinit.send(reply).is_err() {
+                return encode_response(&Response::Error {
+                    error: "reinit isn't available".to_string(),
+                });
+            }
+            let steps = match steps.recv_timeout(REINIT_TIMEOUT) {
+                Ok(steps) => steps,
+                Err(_) => {
+                    return encode_response(&Response::Error {
+                        error: "no bridge to reinit".to_string(),
+                    })
+                }
+            };
+            let steps = steps
+                .into_iter()
+                .map(|step| ReinitStep {
+                    name: step.name,
+                    ok: step.result.is_ok(),
+                    error: step.result.err().map(|e| e.to_string()),
+                })
+                .collect();
+            encode_response(&Response::Reinit { steps })
+        }
+    }
+}
+
+fn encode_response(response: &Response) -> String {
+    let mut line = serde_json::to_string(response)
+        .unwrap_or_else(|e| format!("{{\"error\":\"{}\"}}", e));
+    line.push('\n');
+    line
+}
+
+// Accepts any number of stream clients on CLIENT_SOCKET, broadcasts each
+// message to all of them and answers their requests. Clients that
+// disconnect, or can't take a line within WRITE_TIMEOUT, are dropped.
+pub struct ClientSink {
+    clients: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl ClientSink {
+    // Reinit requests from clients are passed on to `reinit`, see
+    // OpenWyzeHub::set_reinit_requests()
+    pub fn new(reinit: Sender<ReinitRequest>) -> io::Result<ClientSink> {
+        // Left behind by a previous run that didn't exit cleanly
+        let _ = fs::remove_file(CLIENT_SOCKET);
+
+        let listener = UnixListener::bind(CLIENT_SOCKET)?;
+        let clients = Arc::new(Mutex::new(vec![]));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => accept(stream, &accepted, reinit.clone()),
+                    Err(e) => warn!("Failed to accept client: {}", e),
+                }
+            }
+        });
+
+        Ok(ClientSink { clients })
+    }
+
+    fn send_message(&self, message: &ClientMessage) {
+        let mut line = match serde_json::to_string(message) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to encode {:?}: {}", message, e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut clients = lock(&self.clients);
+        clients.retain(|mut client| match client.write_all(line.as_bytes()) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Dropping client: {}", e);
+                false
+            }
+        });
+    }
+}
+
+// A panic while holding the lock mustn't stop the shutdown message
+fn lock(clients: &Mutex<Vec<UnixStream>>) -> std::sync::MutexGuard<'_, Vec<UnixStream>> {
+    match clients.lock() {
+        Ok(clients) => clients,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// Registers a new client and starts a thread answering its requests
+fn accept(
+    stream: UnixStream,
+    clients: &Arc<Mutex<Vec<UnixStream>>>,
+    reinit: Sender<ReinitRequest>,
+) {
+    let reader = match stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .and_then(|_| stream.try_clone())
+    {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn!("Failed to set up client: {}", e);
+            return;
+        }
+    };
+    trace!("Client connected");
+    lock(clients).push(stream);
+
+    let clients = clients.clone();
+    thread::spawn(move || {
+        let mut writer = match reader.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = handle_request(&line, &reinit);
+            // Held so the response isn't interleaved with a broadcast
+            let _clients = lock(&clients);
+            if writer.write_all(response.as_bytes()).is_err() {
+                break;
+            }
+        }
+        trace!("Client disconnected");
+    });
+}
+
+impl Sink for ClientSink {
+    fn send(&self, payload: &PacketPayload) {
+        self.send_message(&ClientMessage::from(payload));
+    }
+
+    fn shutdown(&self, reason: &ShutdownReason) {
+        self.send_message(&ClientMessage::from(reason));
+    }
+}
+
+impl Drop for ClientSink {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(CLIENT_SOCKET);
+    }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum HubError {
+    /// A USB call failed
+    Usb(libusb::Error),
+    /// The device isn't a Wyze bridge
+    NotAHub,
+    /// Nothing is written to the dongle in monitor-only mode
+    MonitorOnly,
+    /// No response with the given id arrived in time
+    Timeout { cmd_id: u8 },
+    /// The dongle answered with something other than what was expected
+    UnexpectedResponse { cmd_id: u8, payload: Vec<u8> },
+    /// An argument was rejected before anything was sent
+    InvalidArgument(String),
+    /// The sensor found while pairing wasn't confirmed, so it wasn't bound
+    PairingDeclined { mac: String },
+}
+
+impl HubError {
+    // Errors after which the device handle is no use any more
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            HubError::Usb(e) => is_fatal(e),
+            _ => false,
+        }
+    }
+}
+
+// USB errors after which the device handle is no use any more
+pub fn is_fatal(e: &libusb::Error) -> bool {
+    matches!(e, libusb::Error::NoDevice | libusb::Error::Io)
+}
+
+impl From<libusb::Error> for HubError {
+    fn from(e: libusb::Error) -> HubError {
+        HubError::Usb(e)
+    }
+}
+
+impl fmt::Display for HubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HubError::Usb(e) => write!(f, "USB error: {}", e),
+            HubError::NotAHub => write!(f, "not a Wyze bridge"),
+            HubError::MonitorOnly => write!(f, "not sending in monitor-only mode"),
+            HubError::Timeout { cmd_id } => write!(f, "timed out waiting for {:02X}", cmd_id),
+            HubError::UnexpectedResponse { cmd_id, payload } => {
+                write!(f, "unexpected {:02X} response: {:02X?}", cmd_id, payload)
+            }
+            HubError::InvalidArgument(what) => write!(f, "invalid argument: {}", what),
+            HubError::PairingDeclined { mac } => write!(f, "not binding {}", mac),
+        }
+    }
+}
+
+impl Error for HubError {}
+
+// libusb::Error is only Debug, so anything holding one that has to be
+// cloned rebuilds it variant by variant
+pub fn copy_usb_error(e: &libusb::Error) -> libusb::Error {
+    match e {
+        libusb::Error::Success => libusb::Error::Success,
+        libusb::Error::Io => libusb::Error::Io,
+        libusb::Error::InvalidParam => libusb::Error::InvalidParam,
+        libusb::Error::Access => libusb::Error::Access,
+        libusb::Error::NoDevice => libusb::Error::NoDevice,
+        libusb::Error::NotFound => libusb::Error::NotFound,
+        libusb::Error::Busy => libusb::Error::Busy,
+        libusb::Error::Timeout => libusb::Error::Timeout,
+        libusb::Error::Overflow => libusb::Error::Overflow,
+        libusb::Error::Pipe => libusb::Error::Pipe,
+        libusb::Error::Interrupted => libusb::Error::Interrupted,
+        libusb::Error::NoMem => libusb::Error::NoMem,
+        libusb::Error::NotSupported => libusb::Error::NotSupported,
+        libusb::Error::Other => libusb::Error::Other,
+    }
+}
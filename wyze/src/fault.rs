@@ -0,0 +1,258 @@
+// Mangles HID reports on their way into the decoder, to check that the
+// pipeline recovers from a misbehaving dongle:
+//
+//     let mut harness = PipelineHarness::new();
+//     harness.set_faults(FaultInjector::new(Scenario {
+//         seed: 7,
+//         corrupt: 10,
+//         ..Scenario::default()
+//     }));
+//
+// Each fault is rolled per report, with the chance given in percent. The
+// same seed always gives the same faults for the same input, so a failing
+// scenario can be replayed.
+//
+// MockDongle answers commands the way the dongle does, with its reports
+// going through a FaultInjector, so link::transact() can be run against a
+// scenario. With `kill` set the dongle can also die mid-command.
+
+use std::collections::VecDeque;
+
+use crate::codec::{self, encode_bridge};
+use crate::error::HubError;
+use crate::link::Link;
+use crate::magic;
+use crate::packet::{PacketSyncType, RawPacket};
+
+// Bridge ack: 55 AA 53 <cmd_id> FF <checksum: 2>
+const ACK_LEN: usize = 7;
+
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub seed: u64,
+    // A random byte of the report is flipped
+    pub corrupt: u32,
+    // The report arrives as two reads
+    pub split: u32,
+    // The report is held back and arrives after the next one
+    pub delay: u32,
+    // The report never arrives
+    pub drop: u32,
+    // Acks in the report are removed
+    pub drop_acks: u32,
+    // MockDongle only: the dongle takes a command and is unplugged before
+    // answering it
+    pub kill: u32,
+}
+
+// Number of faults of each kind injected so far
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    pub corrupted: u64,
+    pub split: u64,
+    pub delayed: u64,
+    pub dropped: u64,
+    pub dropped_acks: u64,
+    pub killed: u64,
+}
+
+pub struct FaultInjector {
+    scenario: Scenario,
+    // xorshift64 state, never 0
+    state: u64,
+    held: Option<Vec<u8>>,
+    faults: Faults,
+}
+
+impl FaultInjector {
+    pub fn new(scenario: Scenario) -> FaultInjector {
+        FaultInjector {
+            state: scenario.seed | 1,
+            scenario,
+            held: None,
+            faults: Faults::default(),
+        }
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    // Turns one report, starting with its count byte, into the reports
+    // that reach the decoder instead
+    pub fn inject(&mut self, report: &[u8]) -> Vec<Vec<u8>> {
+        let mut report = report.to_vec();
+        let mut out = vec![];
+
+        if self.roll(self.scenario.drop) {
+            self.faults.dropped += 1;
+            return self.held.take().into_iter().collect();
+        }
+        if self.roll(self.scenario.drop_acks) {
+            self.faults.dropped_acks += remove_acks(&mut report);
+        }
+        if report.len() > 1 && self.roll(self.scenario.corrupt) {
+            let i = 1 + self.below(report.len() - 1);
+            report[i] ^= 1 << self.below(8);
+            self.faults.corrupted += 1;
+        }
+
+        let mut reports = if report.len() > 2 && self.roll(self.scenario.split) {
+            self.faults.split += 1;
+            let at = 2 + self.below(report.len() - 2);
+            let valid = std::cmp::min(report[0] as usize, report.len() - 1);
+            let head = std::cmp::min(valid, at - 1);
+            let mut tail = vec![(valid - head) as u8];
+            tail.extend_from_slice(&report[at..]);
+            report.truncate(at);
+            report[0] = head as u8;
+            vec![report, tail]
+        } else {
+            vec![report]
+        };
+
+        if self.held.is_none() && self.roll(self.scenario.delay) {
+            self.faults.delayed += 1;
+            self.held = Some(reports.remove(0));
+        } else if let Some(held) = self.held.take() {
+            out.push(held);
+        }
+        out.extend(reports);
+        out
+    }
+
+    fn roll(&mut self, percent: u32) -> bool {
+        percent > 0 && (self.next() % 100) < u64::from(percent)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+// Answers each command with an ack (async commands only) and a response
+// with the next id, echoing the command's payload. Reads with nothing
+// queued time out like the real endpoint, without the wait. Once killed
+// every read and write fails with NoDevice, as after an unplug.
+pub struct MockDongle {
+    injector: FaultInjector,
+    reports: VecDeque<Vec<u8>>,
+    commands: Vec<Vec<u8>>,
+    dead: bool,
+}
+
+impl MockDongle {
+    pub fn new(scenario: Scenario) -> MockDongle {
+        MockDongle {
+            injector: FaultInjector::new(scenario),
+            reports: VecDeque::new(),
+            commands: vec![],
+            dead: false,
+        }
+    }
+
+    pub fn faults(&self) -> &Faults {
+        self.injector.faults()
+    }
+
+    // Every frame written so far, retransmits included
+    pub fn commands(&self) -> &[Vec<u8>] {
+        &self.commands
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.dead
+    }
+
+    // Queues a report the bridge sends on its own, like a sensor event. It
+    // is read before the answers to commands written after it.
+    pub fn push_report(&mut self, report: &[u8]) {
+        let reports = self.injector.inject(report);
+        self.reports.extend(reports);
+    }
+
+    fn answer(&mut self, frame: &magic::Frame) {
+        let mut data = vec![];
+        let packet_type = if frame.cmd_type == 0x53 {
+            let mut ack = vec![0x55, 0xAA, 0x53, frame.cmd_id, 0xFF];
+            let ck = codec::checksum(&ack);
+            ack.extend_from_slice(&[(ck >> 8) as u8, ck as u8]);
+            data.extend(ack);
+            PacketSyncType::Async
+        } else {
+            PacketSyncType::Sync
+        };
+        let response = RawPacket::create(packet_type, frame.cmd_id.wrapping_add(1), &frame.payload);
+        data.extend(encode_bridge(&response));
+
+        let mut report = vec![data.len() as u8];
+        report.extend(data);
+        report.resize(64, 0);
+        let reports = self.injector.inject(&report);
+        self.reports.extend(reports);
+    }
+}
+
+impl Link for MockDongle {
+    fn write(&mut self, data: &[u8]) -> Result<(), HubError> {
+        if self.dead {
+            return Err(HubError::Usb(libusb::Error::NoDevice));
+        }
+        self.commands.push(data.to_vec());
+
+        let kill = self.injector.scenario.kill;
+        if self.injector.roll(kill) {
+            self.injector.faults.killed += 1;
+            self.dead = true;
+            return Ok(());
+        }
+
+        let frames: Vec<_> = magic::decode_all(data).into_iter().filter_map(Result::ok).collect();
+        for frame in frames {
+            self.answer(&frame);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, HubError> {
+        if self.dead {
+            return Err(HubError::Usb(libusb::Error::NoDevice));
+        }
+        let report = self
+            .reports
+            .pop_front()
+            .ok_or(HubError::Usb(libusb::Error::Timeout))?;
+        let len = std::cmp::min(report.len(), buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+}
+
+// Removes bridge acks from the valid bytes of a report and lowers its count
+// to match. Returns the number removed.
+fn remove_acks(report: &mut Vec<u8>) -> u64 {
+    if report.is_empty() {
+        return 0;
+    }
+    let mut end = 1 + std::cmp::min(report[0] as usize, report.len() - 1);
+    let mut removed = 0;
+    let mut i = 1;
+    while i + ACK_LEN <= end {
+        if report[i..i + 3] == [0x55, 0xAA, 0x53] && report[i + 4] == 0xFF {
+            report.drain(i..i + ACK_LEN);
+            end -= ACK_LEN;
+            removed += 1;
+        } else {
+            i += 1;
+        }
+    }
+    report[0] = (end - 1) as u8;
+    removed
+}
@@ -0,0 +1,162 @@
+// Drives the event pipeline without a dongle, for tests here and in
+// downstream crates:
+//
+//     let mut harness = PipelineHarness::new();
+//     harness.add_transform(Box::new(LeakHysteresis::new(2, Duration::from_secs(60))));
+//     let sink = harness.add_sink();
+//     harness.feed(&report);
+//     harness.advance(Duration::from_secs(60));
+//     harness.feed(&report);
+//     assert_eq!(sink.received().len(), 1);
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "fault-injection")]
+use crate::fault::FaultInjector;
+use crate::magic;
+use crate::packet::PacketPayload;
+use crate::sink::Sink;
+use crate::transform::{self, Transform};
+use crate::ShutdownReason;
+
+pub struct PipelineHarness {
+    decoder: magic::FrameDecoder,
+    transforms: Vec<Box<dyn Transform>>,
+    sinks: Vec<Arc<dyn Sink>>,
+    // Fake clock handed to the transforms. Only moves on advance().
+    now: Instant,
+    #[cfg(feature = "fault-injection")]
+    faults: Option<FaultInjector>,
+}
+
+impl PipelineHarness {
+    pub fn new() -> PipelineHarness {
+        PipelineHarness {
+            decoder: magic::FrameDecoder::default(),
+            transforms: vec![],
+            sinks: vec![],
+            now: Instant::now(),
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
+
+    pub fn add_transform(&mut self, transform: Box<dyn Transform>) {
+        transform::insert(&mut self.transforms, transform);
+    }
+
+    // Registers any other sink
+    pub fn register_sink(&mut self, sink: Arc<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    // Registers a sink that records what it receives, and returns it
+    pub fn add_sink(&mut self) -> Arc<RecordingSink> {
+        let sink = Arc::new(RecordingSink::default());
+        self.sinks.push(sink.clone());
+        sink
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.now += by;
+    }
+
+    // Feeds a HID report as read from the dongle, starting with its count
+    // byte. It may hold several frames or part of one.
+    pub fn feed(&mut self, report: &[u8]) {
+        #[cfg(feature = "fault-injection")]
+        {
+            if let Some(faults) = self.faults.as_mut() {
+                for report in faults.inject(report) {
+                    self.decode(&report);
+                }
+                return;
+            }
+        }
+        self.decode(report);
+    }
+
+    fn decode(&mut self, report: &[u8]) {
+        for frame in self.decoder.decode(report) {
+            if let Ok(payload) = magic::parse_payload(&frame) {
+                self.feed_payload(payload);
+            }
+        }
+    }
+
+    // Passes reports given to feed() through `faults` first
+    #[cfg(feature = "fault-injection")]
+    pub fn set_faults(&mut self, faults: FaultInjector) {
+        self.faults = Some(faults);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    pub fn faults(&self) -> Option<&crate::fault::Faults> {
+        self.faults.as_ref().map(FaultInjector::faults)
+    }
+
+    // Frames dropped by the decoder because their checksum didn't match
+    pub fn corrupt_frames(&self) -> u64 {
+        self.decoder.corrupt_frames()
+    }
+
+    // Feeds an already decoded payload
+    pub fn feed_payload(&mut self, payload: PacketPayload) {
+        crate::dispatch(&mut self.transforms, payload, self.now, &self.sinks);
+    }
+
+    pub fn shutdown(&mut self, reason: &ShutdownReason) {
+        for sink in &self.sinks {
+            sink.shutdown(reason);
+        }
+    }
+}
+
+impl Default for PipelineHarness {
+    fn default() -> PipelineHarness {
+        PipelineHarness::new()
+    }
+}
+
+#[derive(Default)]
+pub struct RecordingSink {
+    received: Mutex<Vec<PacketPayload>>,
+    shutdown: Mutex<Option<ShutdownReason>>,
+}
+
+impl RecordingSink {
+    // Everything sent so far, in order
+    pub fn received(&self) -> Vec<PacketPayload> {
+        self.received.lock().unwrap().clone()
+    }
+
+    // Everything sent so far about one sensor, in order
+    pub fn received_from(&self, mac: &str) -> Vec<PacketPayload> {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|payload| payload.mac() == mac)
+            .cloned()
+            .collect()
+    }
+
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        self.shutdown.lock().unwrap().clone()
+    }
+}
+
+impl Sink for RecordingSink {
+    fn send(&self, payload: &PacketPayload) {
+        self.received.lock().unwrap().push(payload.clone());
+    }
+
+    fn shutdown(&self, reason: &ShutdownReason) {
+        *self.shutdown.lock().unwrap() = Some(reason.clone());
+    }
+}
@@ -0,0 +1,976 @@
+extern crate libusb;
+extern crate log;
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, trace, warn};
+use serde::Serialize;
+
+pub mod client;
+mod error;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod harness;
+pub mod link;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod sample;
+pub mod signal;
+pub mod sink;
+pub mod transform;
+
+pub use error::HubError;
+pub use wyze_protocol::{codec, packet, sensor};
+pub use wyze_protocol::{CommandId, Frame, FrameDecoder, PacketSource, ParseError};
+use wyze_protocol::magic;
+use codec::encode;
+use link::Link;
+use packet::*;
+use sample::Sampler;
+use sink::{ChannelSink, Sink};
+use transform::Transform;
+
+const HUB_VENDOR_ID: u16 = 0x1A86;
+const HUB_PRODUCT_ID: u16 = 0xE024;
+
+// Times a command that got neither an ack nor a response is sent again
+const DEFAULT_RETRANSMITS: usize = 2;
+
+// Consecutive failed reads (not counting timeouts) after which run() gives
+// up on the handle
+const READ_FAILURES: usize = 3;
+
+// A checksum failure now and then is line noise, but DEFAULT_CHECKSUM_BURST
+// of them within DEFAULT_CHECKSUM_WINDOW means the stream is out of sync
+pub const DEFAULT_CHECKSUM_BURST: usize = 5;
+pub const DEFAULT_CHECKSUM_WINDOW: Duration = Duration::from_secs(10);
+
+// Random value sent to sensors while pairing. This is the value the
+// official app uses.
+const PAIRING_R1: &[u8; 16] = b"Ok5HPNQ4lf77u754";
+
+// Challenge sent with the ENR request. The official app sends this fixed
+// value too.
+const ENR_CHALLENGE: &[u8; 16] = b"0000000000000000";
+
+pub struct WyzeHub<'a> {
+    device: libusb::Device<'a>,
+}
+
+impl<'a> WyzeHub<'a> {
+    pub fn get_hubs(context: &'a libusb::Context) -> Vec<WyzeHub<'a>> {
+        match context.devices() {
+            Ok(devices) => {
+                let mut hubs = vec![];
+                for device in devices.iter() {
+                    if let Ok(hub) = WyzeHub::new(device) {
+                        hubs.push(hub);
+                    }
+                }
+                hubs
+            }
+            Err(_) => vec![],
+        }
+    }
+
+    // The constructor will only build a WyzeHub instance if the USB handle
+    // corresponds to a valid Wyze Hub
+    pub fn new(device: libusb::Device) -> Result<WyzeHub, HubError> {
+        let device_desc = device.device_descriptor()?;
+
+        if device_desc.vendor_id() == HUB_VENDOR_ID && device_desc.product_id() == HUB_PRODUCT_ID {
+            Ok(WyzeHub { device })
+        } else {
+            Err(HubError::NotAHub)
+        }
+    }
+
+    // Bus number and device address, as shown by lsusb
+    pub fn bus_address(&self) -> (u8, u8) {
+        (self.device.bus_number(), self.device.address())
+    }
+
+    // USB serial number string, if the device has one
+    pub fn serial(&self) -> Option<String> {
+        let timeout = Duration::new(1, 0);
+        let desc = self.device.device_descriptor().ok()?;
+        let handle = self.device.open().ok()?;
+        let language = handle.read_languages(timeout).ok()?.into_iter().next()?;
+        handle.read_serial_number_string(language, &desc, timeout).ok()
+    }
+
+    // Runs the hub on a background thread and delivers what it reads over a
+    // channel: sensor events, alarms and everything else a sink would be
+    // sent. The thread opens its own libusb context and finds this hub
+    // again by bus and address. It stops at the first payload after the
+    // receiver is dropped.
+    pub fn events(&self) -> Receiver<PacketPayload> {
+        let (sink, rx) = ChannelSink::new();
+        let sink = Arc::new(sink);
+        let bus = self.device.bus_number();
+        let address = self.device.address();
+
+        thread::spawn(move || {
+            let context = match libusb::Context::new() {
+                Ok(context) => context,
+                Err(e) => {
+                    warn!("Failed to create libusb context: {:?}", e);
+                    return;
+                }
+            };
+            let hub = WyzeHub::get_hubs(&context).into_iter().find(|hub| {
+                hub.device.bus_number() == bus && hub.device.address() == address
+            });
+            let hub = match hub {
+                Some(hub) => hub,
+                None => {
+                    warn!("Hub at {:03}:{:03} went away", bus, address);
+                    return;
+                }
+            };
+            let mut hub = match hub.open() {
+                Ok(hub) => hub,
+                Err(e) => {
+                    warn!("Failed to open hub: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = hub.init() {
+                warn!("Failed to init hub: {}", e);
+                return;
+            }
+
+            let sinks: [Arc<dyn Sink>; 1] = [sink.clone()];
+            loop {
+                match hub.read_payloads() {
+                    Ok(payloads) => {
+                        for payload in payloads {
+                            dispatch(&mut hub.transforms, payload, Instant::now(), &sinks);
+                        }
+                        if sink.is_closed() {
+                            trace!("Event receiver dropped, stopping");
+                            return;
+                        }
+                    }
+                    Err(e) if e.is_fatal() => {
+                        warn!("Hub lost: {}", e);
+                        return;
+                    }
+                    Err(_) => (),
+                }
+            }
+        });
+
+        rx
+    }
+
+    pub fn open(self) -> Result<OpenWyzeHub<'a>, HubError> {
+        trace!("Open hub");
+        let handle = self.device.open()?;
+        Ok(OpenWyzeHub {
+            _device: self.device,
+            handle,
+            decoder: magic::FrameDecoder::default(),
+            monitor_only: false,
+            dry_run: false,
+            retransmits: DEFAULT_RETRANSMITS,
+            checksum_burst: DEFAULT_CHECKSUM_BURST,
+            checksum_window: DEFAULT_CHECKSUM_WINDOW,
+            checksum_failures: VecDeque::new(),
+            corrupt_seen: 0,
+            desyncs: 0,
+            transforms: vec![],
+            sampler: None,
+            reinit_requests: None,
+            pending: vec![],
+            held: vec![],
+            mac: None,
+        })
+    }
+}
+
+pub struct OpenWyzeHub<'a> {
+    _device: libusb::Device<'a>,
+    handle: libusb::DeviceHandle<'a>,
+    decoder: magic::FrameDecoder,
+    monitor_only: bool,
+    dry_run: bool,
+    // Extra attempts made by transact() for commands that get no answer
+    retransmits: usize,
+    // Desync detection in run(), see set_checksum_tolerance()
+    checksum_burst: usize,
+    checksum_window: Duration,
+    checksum_failures: VecDeque<Instant>,
+    corrupt_seen: u64,
+    desyncs: u64,
+    // Applied in order to payloads in run()
+    transforms: Vec<Box<dyn Transform>>,
+    sampler: Option<Arc<Sampler>>,
+    reinit_requests: Option<Arc<Mutex<Receiver<ReinitRequest>>>>,
+    // Read while draining for reinit(), handed out by the next
+    // read_payloads()
+    pending: Vec<PacketPayload>,
+    // Frames that arrived while a command waited for its reply, decoded by
+    // the next read_payloads() before anything new is read
+    held: Vec<magic::Frame>,
+    // Filled in by the handshake
+    mac: Option<String>,
+}
+
+/// Asks run() to redo the handshake, see OpenWyzeHub::set_reinit_requests().
+/// The steps are sent back over the channel.
+pub type ReinitRequest = SyncSender<Vec<HandshakeStep>>;
+/// Why run() stopped
+#[derive(Debug)]
+pub enum ShutdownReason {
+    // The dongle was unplugged or the bus failed
+    DeviceLost(libusb::Error),
+    Panic(String),
+    // SIGINT or SIGTERM, see signal.rs
+    Signal(i32),
+}
+
+impl ShutdownReason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ShutdownReason::DeviceLost(_) => "device_lost",
+            ShutdownReason::Panic(_) => "panic",
+            ShutdownReason::Signal(_) => "signal",
+        }
+    }
+}
+
+impl Clone for ShutdownReason {
+    fn clone(&self) -> ShutdownReason {
+        match self {
+            ShutdownReason::DeviceLost(e) => ShutdownReason::DeviceLost(error::copy_usb_error(e)),
+            ShutdownReason::Panic(message) => ShutdownReason::Panic(message.clone()),
+            ShutdownReason::Signal(signal) => ShutdownReason::Signal(*signal),
+        }
+    }
+}
+
+impl PartialEq for ShutdownReason {
+    fn eq(&self, other: &ShutdownReason) -> bool {
+        match (self, other) {
+            (ShutdownReason::DeviceLost(a), ShutdownReason::DeviceLost(b)) => {
+                std::mem::discriminant(a) == std::mem::discriminant(b)
+            }
+            (ShutdownReason::Panic(a), ShutdownReason::Panic(b)) => a == b,
+            (ShutdownReason::Signal(a), ShutdownReason::Signal(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Result of a selftest round trip
+#[derive(Debug, Serialize)]
+pub struct SelfTest {
+    // The inquiry frame was written to the dongle
+    pub sent: bool,
+    // A well formed 0x28 response was read back
+    pub response: bool,
+    pub latency_ms: Option<u64>,
+    // First payload byte of the response, 1 when the dongle is ready
+    pub status: Option<u8>,
+}
+
+impl SelfTest {
+    pub fn passed(&self) -> bool {
+        self.sent && self.response
+    }
+}
+
+/// One value read back by OpenWyzeHub::diagnostics()
+#[derive(Debug)]
+pub struct DiagReading {
+    pub name: &'static str,
+    // Id of the response the value came from
+    pub cmd_id: u8,
+    // Response payload
+    pub result: Result<Vec<u8>, HubError>,
+    // Meaning of the payload, where it's known
+    pub decoded: Option<String>,
+}
+
+/// Outcome of a single step of the init handshake
+#[derive(Debug)]
+pub struct HandshakeStep {
+    pub name: &'static str,
+    pub result: Result<(), HubError>,
+}
+
+impl<'a> OpenWyzeHub<'a> {
+    // In monitor-only mode the hub is never reset and nothing is ever
+    // written to it; only reads are performed. This allows passively
+    // listening to a dongle that is managed by something else.
+    pub fn set_monitor_only(&mut self, monitor_only: bool) {
+        self.monitor_only = monitor_only;
+    }
+
+    // In dry-run mode frames are printed instead of being sent, and every
+    // command is assumed to succeed. Commands run through their usual steps,
+    // so the output shows exactly what would have been sent. Like
+    // monitor-only mode, the hub isn't reset and no handshake is done.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    // Number of times a command is sent again when neither an ack nor its
+    // response arrives in time
+    pub fn set_retransmits(&mut self, retransmits: usize) {
+        self.retransmits = retransmits;
+    }
+
+    // run() treats `burst` checksum failures within `window` as a loss of
+    // sync: the decoder is flushed and the handshake redone. A burst of 0
+    // never does.
+    pub fn set_checksum_tolerance(&mut self, burst: usize, window: Duration) {
+        self.checksum_burst = burst;
+        self.checksum_window = window;
+    }
+
+    // Adds a transform run on payloads before they reach the sinks, see
+    // transform::Stage for the order they run in
+    pub fn add_transform(&mut self, transform: Box<dyn Transform>) {
+        transform::insert(&mut self.transforms, transform);
+    }
+
+    // Offers every frame read_payloads() decodes to `sampler`
+    pub fn set_sampler(&mut self, sampler: Arc<Sampler>) {
+        self.sampler = Some(sampler);
+    }
+
+    // run() redoes the handshake for each request that arrives on
+    // `requests`, between reads. Shared between successive OpenWyzeHubs, so
+    // requests made while the bridge is away are served once it is back.
+    pub fn set_reinit_requests(&mut self, requests: Arc<Mutex<Receiver<ReinitRequest>>>) {
+        self.reinit_requests = Some(requests);
+    }
+
+    // Fails if the USB setup fails. Handshake failures are reported in the
+    // returned steps instead.
+    pub fn init(&mut self) -> Result<Vec<HandshakeStep>, HubError> {
+        if !self.monitor_only && !self.dry_run {
+            trace!("Reset");
+            self.handle.reset()?;
+        }
+
+        trace!("Set active config");
+        self.handle.set_active_configuration(0x00)?;
+
+        trace!("Claim interface");
+        self.handle.claim_interface(0x0000)?;
+
+        trace!("USB HID setup complete");
+
+        if self.monitor_only {
+            trace!("Monitor-only mode, skipping handshake");
+            return Ok(vec![]);
+        }
+        if self.dry_run {
+            trace!("Dry-run mode, skipping handshake");
+            return Ok(vec![]);
+        }
+
+        Ok(self.handshake())
+    }
+
+    // Re-runs the init handshake on the already claimed handle. Useful when
+    // the dongle drifts into a bad state; the USB device is not reset.
+    // Reports already waiting are read first, and what they decode to is
+    // returned by the next read_payloads() as usual. So are frames that
+    // arrive during the handshake, like a sensor event.
+    pub fn reinit(&mut self) -> Vec<HandshakeStep> {
+        trace!("Reinit: draining pending reports");
+        let mut drained = std::mem::take(&mut self.pending);
+        for _ in 0..16 {
+            match self.read_payloads() {
+                Ok(payloads) => drained.extend(payloads),
+                Err(_) => break,
+            }
+        }
+        self.pending = drained;
+
+        let steps = self.handshake();
+        for step in &steps {
+            trace!("Reinit step {}: {:?}", step.name, step.result);
+        }
+        steps
+    }
+
+    fn handshake(&mut self) -> Vec<HandshakeStep> {
+        let mut steps = vec![];
+
+        steps.push(self.command_step("inquiry", InquiryPacket));
+
+        // The ENR and key replies aren't kept. Nothing uses them, and there
+        // is no known way to check them against the challenge.
+        let enr = self.request(
+            "get_enr",
+            EnrPacket::create(*ENR_CHALLENGE),
+            CommandId::GetEnrResponse.into(),
+        );
+        steps.push(HandshakeStep {
+            name: "get_enr",
+            result: enr.map(|_| ()),
+        });
+
+        let mac = self.request("get_mac", GetMacPacket, CommandId::GetMacResponse.into());
+        steps.push(HandshakeStep {
+            name: "get_mac",
+            result: mac.map(|mac| self.mac = String::from_utf8(mac).ok()),
+        });
+
+        let key = self.request("get_key", GetKeyPacket, CommandId::GetKeyResponse.into());
+        steps.push(HandshakeStep {
+            name: "get_key",
+            result: key.map(|_| ()),
+        });
+
+        steps.push(self.command_step("get_ver", GetVerPacket));
+        steps.push(self.command_step("get_sensor_count", GetSensorCountPacket));
+        steps.push(self.command_step("get_sensor_list", GetSensorListPacket::create(5)));
+        let _ = self.raw_read();
+        let _ = self.raw_read();
+
+        steps.push(HandshakeStep {
+            name: "auth",
+            result: self.send(AuthPacket::create_done()),
+        });
+
+        trace!("Hub setup complete");
+        steps
+    }
+
+    // Sends a command and reads one report back
+    fn command_step<P>(&mut self, name: &'static str, packet: P) -> HandshakeStep
+        where P: Packet + Packable + Debug
+    {
+        let result = match self.send(packet) {
+            Ok(()) => self.raw_read().map(|_| ()),
+            Err(e) => {
+                warn!("Failed to send {}: {}", name, e);
+                Err(e)
+            }
+        };
+        HandshakeStep { name, result }
+    }
+
+    // Sends a command and returns the payload of its response
+    fn request<P>(
+        &mut self,
+        name: &'static str,
+        packet: P,
+        response_id: u8,
+    ) -> Result<Vec<u8>, HubError>
+        where P: Packet + Packable + Debug
+    {
+        self.transact(packet, response_id, Duration::new(1, 0))
+            .map(|frame| frame.payload)
+            .map_err(|e| {
+                warn!("{} failed: {}", name, e);
+                e
+            })
+    }
+
+    // Asks the dongle for its mac on the claimed interface, without the
+    // reset and handshake init() does. For telling bridges apart before
+    // picking the one to initialise. The interface is released again, so
+    // init() can still set the configuration.
+    pub fn read_mac(&mut self) -> Result<String, HubError> {
+        self.handle.claim_interface(0x0000)?;
+        let mac = self.request("get_mac", GetMacPacket, CommandId::GetMacResponse.into());
+        let _ = self.handle.release_interface(0x0000);
+        match String::from_utf8(mac?) {
+            Ok(mac) => {
+                self.mac = Some(mac.clone());
+                Ok(mac)
+            }
+            Err(e) => Err(HubError::UnexpectedResponse {
+                cmd_id: CommandId::GetMacResponse.into(),
+                payload: e.into_bytes(),
+            }),
+        }
+    }
+
+    // The dongle's own mac, as returned during the handshake
+    pub fn mac(&self) -> Option<&str> {
+        self.mac.as_deref()
+    }
+
+    // Times run() found the stream out of sync and recovered
+    pub fn desyncs(&self) -> u64 {
+        self.desyncs
+    }
+
+    // Host frames echoed back on the IN endpoint and dropped so far
+    pub fn host_echoes(&self) -> u64 {
+        self.decoder.host_echoes()
+    }
+
+    // Frames dropped so far because their checksum didn't match
+    pub fn corrupt_frames(&self) -> u64 {
+        self.decoder.corrupt_frames()
+    }
+
+    // Reads one report and returns the frames in it that could be decoded.
+    // Frames held while waiting for a reply are decoded first, instead of
+    // reading. Time sync requests are answered along the way.
+    pub fn read_payloads(&mut self) -> Result<Vec<PacketPayload>, HubError> {
+        if !self.pending.is_empty() {
+            return Ok(std::mem::take(&mut self.pending));
+        }
+        let frames = if self.held.is_empty() {
+            self.raw_read()?
+        } else {
+            std::mem::take(&mut self.held)
+        };
+        let mut payloads = vec![];
+        for frame in frames {
+            if frame.cmd_id == u8::from(CommandId::SyncTime) && !frame.ack {
+                self.sync_time();
+                continue;
+            }
+            let decoded = magic::parse_payload(&frame);
+            if let Some(sampler) = &self.sampler {
+                sampler.offer(&frame, &decoded);
+            }
+            match decoded {
+                Ok(payload) => payloads.push(payload),
+                Err(e) => trace!("Not decoding {:02X}: {}", frame.cmd_id, e),
+            }
+        }
+        Ok(payloads)
+    }
+
+    // Reads one report and returns the sensor events decoded from it
+    pub fn read_events(&mut self) -> Result<Vec<SensorEvent>, HubError> {
+        Ok(self
+            .read_payloads()?
+            .into_iter()
+            .filter_map(|payload| match payload {
+                PacketPayload::SensorEvent(packet) => Some(packet.event),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Puts the network in join mode and pairs the first sensor that shows up
+    // within `timeout`. Join mode is always left again before returning.
+    //
+    // Pairing pauses once the sensor is found: `confirm` is shown what was
+    // found and the sensor is only bound if it returns true. Any sensor in
+    // range that is being paired can answer first, so this is the chance to
+    // check the mac against the label on the one in hand.
+    pub fn start_pairing<F>(
+        &mut self,
+        timeout: Duration,
+        confirm: F,
+    ) -> Result<SensorScanPacket, HubError>
+        where F: FnOnce(&SensorScanPacket) -> bool
+    {
+        trace!("Entering join mode");
+        let joined = CommandId::StartStopNetworkResponse.into();
+        let start = StartStopNetworkPacket::create(true);
+        self.transact(start, joined, Duration::new(1, 0))?;
+
+        let result = self.pair_next(timeout, confirm);
+
+        trace!("Leaving join mode");
+        let stop = StartStopNetworkPacket::create(false);
+        let stopped = self.transact(stop, joined, Duration::new(1, 0));
+        if stopped.is_err() {
+            warn!("Failed to leave join mode");
+        }
+
+        result
+    }
+
+    fn pair_next<F>(&mut self, timeout: Duration, confirm: F) -> Result<SensorScanPacket, HubError>
+        where F: FnOnce(&SensorScanPacket) -> bool
+    {
+        // Join mode was never entered, so stand in a sensor that shows up
+        // right away
+        let scan = if self.dry_run {
+            SensorScanPacket {
+                mac: "00000000".to_string(),
+                sensor_type: sensor::SensorType::Contact,
+                version: 0,
+            }
+        } else {
+            let frame = self.wait_for(CommandId::SensorScan.into(), timeout)?;
+            match magic::parse_sensor_scan(&frame) {
+                Ok(scan) => scan,
+                Err(e) => {
+                    warn!("Bad scan result: {}", e);
+                    return Err(HubError::UnexpectedResponse {
+                        cmd_id: frame.cmd_id,
+                        payload: frame.payload,
+                    })
+                }
+            }
+        };
+        info!("Found sensor {} ({:?})", scan.mac, scan.sensor_type);
+
+        // Nothing has been sent to the sensor yet, so declining leaves it
+        // unbound
+        if !confirm(&scan) {
+            return Err(HubError::PairingDeclined { mac: scan.mac });
+        }
+
+        let r1 = SetRandomPacket::create(&scan.mac, *PAIRING_R1);
+        self.transact(r1, CommandId::SetRandomResponse.into(), Duration::new(5, 0))?;
+
+        let verify = VerifySensorPacket::create(&scan.mac);
+        self.transact(verify, CommandId::VerifySensorResponse.into(), Duration::new(10, 0))?;
+
+        info!("Paired sensor {}", scan.mac);
+        Ok(scan)
+    }
+
+    // Sends an inquiry and times the response. This exercises the whole path:
+    // TX framing and checksum (the dongle ignores bad frames), and RX
+    // reassembly and parsing of the answer.
+    pub fn selftest(&mut self) -> SelfTest {
+        let mut result = SelfTest {
+            sent: false,
+            response: false,
+            latency_ms: None,
+            status: None,
+        };
+
+        let start = Instant::now();
+        if let Err(e) = self.send(InquiryPacket) {
+            warn!("Selftest: failed to send inquiry: {}", e);
+            return result;
+        }
+        result.sent = true;
+
+        if let Ok(frame) = self.wait_for(CommandId::InquiryResponse.into(), Duration::new(2, 0)) {
+            let elapsed = start.elapsed();
+            result.response = true;
+            result.latency_ms =
+                Some(elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()));
+            result.status = frame.payload.first().cloned();
+        }
+        result
+    }
+
+    // Queries what the dongle reports about itself. Payloads whose layout is
+    // known are decoded, others are only returned raw.
+    pub fn diagnostics(&mut self) -> Vec<DiagReading> {
+        let mut readings = vec![];
+
+        // 1 once the dongle is ready
+        let inquiry = self.request("inquiry", InquiryPacket, CommandId::InquiryResponse.into());
+        let decoded = match inquiry {
+            Ok(ref payload) => payload.first().map(|status| format!("status {}", status)),
+            Err(_) => None,
+        };
+        readings.push(DiagReading {
+            name: "inquiry",
+            cmd_id: CommandId::InquiryResponse.into(),
+            result: inquiry,
+            decoded,
+        });
+
+        // ASCII, e.g. "0.0.0.30 V1.4 Dongle UD3U"
+        let version = self.request("get_ver", GetVerPacket, CommandId::GetVersionResponse.into());
+        let decoded = match version {
+            Ok(ref payload) => String::from_utf8(payload.clone()).ok(),
+            Err(_) => None,
+        };
+        readings.push(DiagReading {
+            name: "version",
+            cmd_id: CommandId::GetVersionResponse.into(),
+            result: version,
+            decoded,
+        });
+
+        let count = self.request(
+            "get_sensor_count",
+            GetSensorCountPacket,
+            CommandId::GetSensorCountResponse.into(),
+        );
+        let decoded = match count {
+            Ok(ref payload) => payload.first().map(|count| format!("{} sensor(s)", count)),
+            Err(_) => None,
+        };
+        readings.push(DiagReading {
+            name: "sensor_count",
+            cmd_id: CommandId::GetSensorCountResponse.into(),
+            result: count,
+            decoded,
+        });
+
+        readings
+    }
+
+    // Sends an arbitrary command and returns the payload of its <id + 1>
+    // response, for exploring commands that haven't been mapped yet. Ids in
+    // UNPROBEABLE are refused, see check_probe().
+    pub fn probe(
+        &mut self,
+        packet_type: PacketSyncType,
+        cmd_id: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, HubError> {
+        check_probe(cmd_id)?;
+        let packet = RawPacket::create(packet_type, cmd_id, payload);
+        self.request("probe", packet, cmd_id.wrapping_add(1))
+    }
+
+    // Unbinds a sensor from the dongle. The dongle answers with 0x26 carrying
+    // the mac back followed by a status byte, FF on success.
+    pub fn remove_sensor(&mut self, mac: &str) -> Result<(), HubError> {
+        if mac.len() != 8 {
+            return Err(HubError::InvalidArgument(format!("sensor mac {:?}", mac)));
+        }
+
+        let delete = DeleteSensorCommandPacket::create(mac);
+        let deleted = CommandId::DeleteSensorResponse.into();
+        let frame = self.transact(delete, deleted, Duration::new(5, 0))?;
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if frame.payload.len() != 9
+            || &frame.payload[..8] != mac.as_bytes()
+            || frame.payload[8] != 0xFF
+        {
+            warn!("Failed to remove {}: {:02X?}", mac, frame.payload);
+            return Err(HubError::UnexpectedResponse {
+                cmd_id: frame.cmd_id,
+                payload: frame.payload,
+            });
+        }
+
+        info!("Removed sensor {}", mac);
+        Ok(())
+    }
+
+    // Reads until a (non-ack) frame with the given id arrives. Other frames
+    // read in the meantime are held for read_payloads().
+    fn wait_for(&mut self, cmd_id: u8, timeout: Duration) -> Result<magic::Frame, HubError> {
+        self.wait_matching(cmd_id, timeout, |frame| !frame.ack && frame.cmd_id == cmd_id)
+    }
+
+    // Sends a command and waits for its response, see link::transact()
+    fn transact<P>(
+        &mut self,
+        packet: P,
+        response_id: u8,
+        timeout: Duration,
+    ) -> Result<magic::Frame, HubError>
+        where P: Packet + Packable + Debug
+    {
+        let data = encode(&packet);
+        if self.dry_run {
+            self.write_packet(&packet, &data)?;
+            return Ok(magic::Frame {
+                source: PacketSource::Bridge,
+                cmd_type: 0x53,
+                cmd_id: response_id,
+                ack: false,
+                payload: vec![],
+            });
+        }
+        if self.monitor_only {
+            warn!("Monitor-only mode, not sending {:?}", packet);
+            return Err(HubError::MonitorOnly);
+        }
+
+        trace!("Sending packet {:?}", packet);
+        link::transact(
+            &mut self.handle,
+            &mut self.decoder,
+            &data,
+            response_id,
+            timeout,
+            self.retransmits,
+            &mut self.held,
+        )
+    }
+
+    fn wait_matching<F>(
+        &mut self,
+        cmd_id: u8,
+        timeout: Duration,
+        matches: F,
+    ) -> Result<magic::Frame, HubError>
+        where F: FnMut(&magic::Frame) -> bool
+    {
+        link::wait_matching(
+            &mut self.handle,
+            &mut self.decoder,
+            cmd_id,
+            timeout,
+            &mut self.held,
+            matches,
+        )
+    }
+
+    // Logs and forwards events until the dongle goes away, reads keep
+    // failing or a signal arrives. Sinks aren't told why it stopped; that's
+    // up to the caller, which knows whether it is about to reconnect.
+    pub fn run(&mut self, sinks: &[Arc<dyn Sink>]) -> ShutdownReason {
+        let mut failures = 0;
+        let reason = loop {
+            if let Some(signal) = signal::received() {
+                break ShutdownReason::Signal(signal);
+            }
+            if let Some(reply) = self.next_reinit_request() {
+                let _ = reply.send(self.reinit());
+            }
+            match self.read_payloads() {
+                Ok(payloads) => {
+                    failures = 0;
+                    for payload in payloads {
+                        info!("{:?}", payload);
+                        dispatch(&mut self.transforms, payload, Instant::now(), sinks);
+                    }
+                }
+                Err(HubError::Usb(libusb::Error::Timeout)) => (),
+                Err(HubError::Usb(e)) => {
+                    failures += 1;
+                    warn!("Read failed ({:?}), {}/{}", e, failures, READ_FAILURES);
+                    if error::is_fatal(&e) || failures >= READ_FAILURES {
+                        break ShutdownReason::DeviceLost(e);
+                    }
+                }
+                Err(_) => (),
+            }
+            self.check_sync();
+        };
+
+        warn!("Stopped: {:?}", reason);
+        reason
+    }
+
+    fn next_reinit_request(&self) -> Option<ReinitRequest> {
+        let requests = self.reinit_requests.as_ref()?;
+        let requests = match requests.lock() {
+            Ok(requests) => requests,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        requests.try_recv().ok()
+    }
+
+    // Counts new checksum failures and recovers once there are too many
+    // within the window. The handshake isn't redone in monitor-only or
+    // dry-run mode, where nothing can be sent.
+    fn check_sync(&mut self) {
+        let now = Instant::now();
+        let corrupt = self.decoder.corrupt_frames();
+        for _ in self.corrupt_seen..corrupt {
+            self.checksum_failures.push_back(now);
+        }
+        self.corrupt_seen = corrupt;
+
+        while let Some(&oldest) = self.checksum_failures.front() {
+            if now.duration_since(oldest) <= self.checksum_window {
+                break;
+            }
+            self.checksum_failures.pop_front();
+        }
+
+        if self.checksum_burst == 0 || self.checksum_failures.len() < self.checksum_burst {
+            return;
+        }
+
+        warn!(
+            "{} checksum failures within {:?}, resyncing",
+            self.checksum_failures.len(),
+            self.checksum_window
+        );
+        self.checksum_failures.clear();
+        self.desyncs += 1;
+        self.decoder.clear();
+        if !self.monitor_only && !self.dry_run {
+            self.reinit();
+        }
+    }
+
+    // Answers a time sync request (0x32) with the current time
+    fn sync_time(&mut self) {
+        let response = SyncTimeResponsePacket::now();
+        if let Err(e) = self.send(response) {
+            warn!("Failed to answer time sync request: {}", e);
+        }
+    }
+
+    fn send<P>(&mut self, packet: P) -> Result<(), HubError>
+        where P: Packet + Packable + Debug
+    {
+        trace!("Sending packet {:?}", packet);
+        self.write_packet(&packet, &encode(&packet))
+    }
+
+    // Writes an encoded packet, or in dry-run mode prints it annotated with
+    // the packet it came from
+    fn write_packet<P>(&mut self, packet: &P, data: &[u8]) -> Result<(), HubError>
+        where P: Debug
+    {
+        if self.dry_run {
+            println!("{:02X?}    ; {:?}", data, packet);
+            return Ok(());
+        }
+        self.raw_write(data)
+    }
+
+    fn raw_write(&mut self, data: &[u8]) -> Result<(), HubError> {
+        if self.monitor_only {
+            warn!("Monitor-only mode, not sending {:x?}", &data);
+            return Err(HubError::MonitorOnly);
+        }
+
+        trace!("Sending data {:x?}", &data);
+        self.handle.write(data)
+    }
+
+    fn raw_read(&mut self) -> Result<Vec<magic::Frame>, HubError> {
+        link::read_frames(&mut self.handle, &mut self.decoder)
+    }
+}
+
+// Commands probe() won't send. The firmware update commands leave the
+// dongle in its bootloader with no known way back, and a delete unpairs a
+// sensor.
+pub const UNPROBEABLE: [CommandId; 3] = [
+    CommandId::SetCh554Upgrade,
+    CommandId::UpdateCc1310,
+    CommandId::DeleteSensor,
+];
+
+// Fails for command ids in UNPROBEABLE
+pub fn check_probe(cmd_id: u8) -> Result<(), HubError> {
+    match UNPROBEABLE.iter().find(|&&id| u8::from(id) == cmd_id) {
+        Some(id) => Err(HubError::InvalidArgument(format!(
+            "won't probe {:02X} ({:?})",
+            cmd_id, id
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Runs a payload through the transforms and hands whatever comes out to
+// every sink. Shared by run(), events() and the pipeline harness.
+pub(crate) fn dispatch(
+    transforms: &mut [Box<dyn Transform>],
+    payload: PacketPayload,
+    now: Instant,
+    sinks: &[Arc<dyn Sink>],
+) {
+    if let Some(payload) = transform::apply_all(transforms, payload, now) {
+        for sink in sinks {
+            sink.send(&payload);
+        }
+    }
+}
+
@@ -0,0 +1,164 @@
+// The dongle as seen by transact(): encoded frames go out as control
+// transfers, HID reports come back from the interrupt endpoint.
+// OpenWyzeHub talks to libusb through it, and fault::MockDongle stands in
+// for the dongle in tests.
+
+use std::time::{Duration, Instant};
+
+use log::{trace, warn};
+
+use crate::error::HubError;
+use crate::magic::{Frame, FrameDecoder};
+
+// Number of times a failed control transfer is retried before giving up
+const WRITE_RETRIES: usize = 3;
+
+pub trait Link {
+    // Writes one encoded frame
+    fn write(&mut self, data: &[u8]) -> Result<(), HubError>;
+
+    // Reads one HID report, starting with its count byte, into `buf` and
+    // returns its length
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, HubError>;
+}
+
+// Transient failures (stalls, timeouts) are retried a bounded number of
+// times. A stall on the control endpoint clears itself with the next setup
+// packet, so the retry is all the recovery it needs.
+impl<'a> Link for libusb::DeviceHandle<'a> {
+    fn write(&mut self, data: &[u8]) -> Result<(), HubError> {
+        let mut attempt = 0;
+        loop {
+            let rsp = self.write_control(
+                0x21,   // LIBUSB_REQUEST_TYPE_CLASS | LIBUSB_RECIPIENT_INTERFACE | LIBUSB_ENDPOINT_OUT
+                0x09,   // HID SET_REPORT
+                0x02AA, // Report number 0xAA
+                0x0000,
+                data,
+                Duration::new(1, 0),
+            );
+
+            match rsp {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < WRITE_RETRIES && is_transient(&e) => {
+                    attempt += 1;
+                    match e {
+                        libusb::Error::Pipe => {
+                            warn!("Write stalled, retry {}/{}", attempt, WRITE_RETRIES)
+                        }
+                        e => warn!("Write failed ({:?}), retry {}/{}", e, attempt, WRITE_RETRIES),
+                    }
+                }
+                Err(e) => {
+                    warn!("Write failed after {} attempt(s): {:?}", attempt + 1, e);
+                    return Err(HubError::Usb(e));
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, HubError> {
+        Ok(self.read_interrupt(0x82, buf, Duration::new(1, 0))?)
+    }
+}
+
+fn is_transient(e: &libusb::Error) -> bool {
+    matches!(e, libusb::Error::Pipe | libusb::Error::Timeout | libusb::Error::Interrupted)
+}
+
+// Reads one report and returns the frames it completes
+pub fn read_frames<L>(link: &mut L, decoder: &mut FrameDecoder) -> Result<Vec<Frame>, HubError>
+    where L: Link + ?Sized
+{
+    let mut buf = [0; 64];
+    let len = link.read(&mut buf)?;
+    let rsp = &buf[..len];
+    trace!("Read {:?}: {:X?}", rsp.len(), &rsp);
+    Ok(decoder.decode(rsp))
+}
+
+// Sends an encoded command and waits for its response. Async commands are
+// acked by the dongle (<cmd_id> FF) before the response arrives. If neither
+// shows up in time the command is sent again, up to `retransmits` times. A
+// command that was acked is never repeated, since the dongle has already
+// acted on it. Other frames read while waiting are added to `unmatched`.
+pub fn transact<L>(
+    link: &mut L,
+    decoder: &mut FrameDecoder,
+    data: &[u8],
+    response_id: u8,
+    timeout: Duration,
+    retransmits: usize,
+    unmatched: &mut Vec<Frame>,
+) -> Result<Frame, HubError>
+    where L: Link + ?Sized
+{
+    let cmd_id = data.get(4).cloned().unwrap_or_default();
+
+    let mut attempt = 0;
+    loop {
+        trace!("Sending data {:x?}, attempt {}", data, attempt + 1);
+        link.write(data)?;
+
+        let mut acked = false;
+        let result = wait_matching(link, decoder, response_id, timeout, unmatched, |frame| {
+            if frame.ack && frame.cmd_id == cmd_id {
+                trace!("{:02X} acked", cmd_id);
+                acked = true;
+                return false;
+            }
+            !frame.ack && frame.cmd_id == response_id
+        });
+
+        match result {
+            Err(HubError::Timeout { .. }) if !acked && attempt < retransmits => {
+                attempt += 1;
+                warn!("No answer to {:02X}, retransmit {}/{}", cmd_id, attempt, retransmits);
+            }
+            result => return result,
+        }
+    }
+}
+
+// Reads until a frame accepted by `matches` arrives. Other frames read in
+// the meantime, including any that follow it in the same report, are added
+// to `unmatched` so the caller can hand them on; acks are left out, they
+// only mean something to the command waiting on them. `cmd_id` is only used
+// for the timeout error. A fatal error, like the dongle being unplugged,
+// ends the wait right away.
+pub fn wait_matching<L, F>(
+    link: &mut L,
+    decoder: &mut FrameDecoder,
+    cmd_id: u8,
+    timeout: Duration,
+    unmatched: &mut Vec<Frame>,
+    mut matches: F,
+) -> Result<Frame, HubError>
+    where L: Link + ?Sized,
+          F: FnMut(&Frame) -> bool
+{
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match read_frames(link, decoder) {
+            Ok(frames) => {
+                let mut found = None;
+                for frame in frames {
+                    if found.is_none() && matches(&frame) {
+                        found = Some(frame);
+                    } else if !frame.ack {
+                        trace!("Holding {:?} while waiting for {:02X}", frame, cmd_id);
+                        unmatched.push(frame);
+                    }
+                }
+                if let Some(frame) = found {
+                    return Ok(frame);
+                }
+            }
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(_) => (),
+        }
+    }
+
+    warn!("Timed out waiting for {:02X}", cmd_id);
+    Err(HubError::Timeout { cmd_id })
+}
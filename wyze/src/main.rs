@@ -1,671 +1,612 @@
-extern crate libusb;
-extern crate log;
-#[macro_use]
-extern crate nom;
 extern crate simple_logger;
+extern crate wyze;
 
-use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use log::trace;
-use bytes::{Bytes, BytesMut};
-use bytes::BufMut;
+use wyze::client::{ClientSink, CLIENT_SOCKET};
+use wyze::codec::hex;
+use wyze::signal;
+use wyze::sink::{QueuedSink, Sink};
+use wyze::transform::{AlarmDedupe, LeakHysteresis};
+use wyze::packet::{PacketSyncType, SensorScanPacket};
+use wyze::sample::Sampler;
+use wyze::{HubError, OpenWyzeHub, ShutdownReason, WyzeHub};
 
-mod magic;
+// How long `pair` keeps the network in join mode
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(60);
 
-const HUB_VENDOR_ID: u16 = 0x1A86;
-const HUB_PRODUCT_ID: u16 = 0xE024;
+// How long repeats of a reading are dropped for, unless --dedupe-secs is given
+const DEDUPE_WINDOW: Duration = Duration::from_secs(5);
 
-pub struct WyzeHub<'a> {
-    device: libusb::Device<'a>,
-}
-
-impl<'a> WyzeHub<'a> {
-    pub fn get_hubs(context: &'a libusb::Context) -> Vec<WyzeHub<'a>> {
-        match context.devices() {
-            Ok(devices) => {
-                let mut hubs = vec![];
-                for device in devices.iter() {
-                    match WyzeHub::new(device) {
-                        Ok(hub) => hubs.push(hub),
-                        Err(_) => (),
-                    }
-                }
-                return hubs;
-            }
-            Err(_) => return vec![],
-        }
-    }
-
-    // The constructor will only build a WyzeHub instance if the USB handle
-    // corresponds to a valid Wyze Hub
-    pub fn new(device: libusb::Device) -> Result<WyzeHub, ()> {
-        let device_desc = device.device_descriptor().map_err(|_| ())?;
-
-        if device_desc.vendor_id() == HUB_VENDOR_ID && device_desc.product_id() == HUB_PRODUCT_ID {
-            return Ok(WyzeHub { device });
-        } else {
-            return Err(());
-        }
-    }
-
-    pub fn open(self) -> OpenWyzeHub<'a> {
-        trace!("Open hub");
-        let handle = self.device.open().unwrap();
-        OpenWyzeHub {
-            _device: self.device,
-            handle: handle,
-            buf: [0; 64],
-        }
-    }
-}
+// Where --sample-every writes to, unless --sample-file is given
+const SAMPLE_FILE: &str = "wyze-samples.jsonl";
 
-pub struct OpenWyzeHub<'a> {
-    _device: libusb::Device<'a>,
-    handle: libusb::DeviceHandle<'a>,
-    buf: [u8; 64],
-}
+// How often to look for a bridge while none is plugged in
+const HOTPLUG_POLL: Duration = Duration::from_secs(1);
 
-#[derive(Debug)]
-pub enum PacketSyncType {
-    Sync,
-    Async,
-}
+// Delay before reopening a bridge that failed, doubling up to MAX_BACKOFF
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
-pub struct ReceivedPacket<T>
-    where T: Packet 
-{
-    pub lqi: u8,
-    pub packet_type: PacketType,
-    pub packet: T
+struct Backoff {
+    delay: Duration,
 }
 
-impl<T> ReceivedPacket<T>
-    where T: Packet
-{
-    pub fn into_inner(self) -> T {
-        self.packet
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff { delay: MIN_BACKOFF }
     }
-}
-
-pub enum PacketType {
-    GetEnr,
-    Auth,
-    GetMac,
-    GetKey,
-    Inquiry,
-    GetVer,
-    GetSensorCount,
-    SetRandom,
-    StartStopNetwork,
-    GetSensorList,
-    Event,
-    AddSensor,
-    Ack,
-}
-
-pub trait Packet {
-    fn get_packet_type(&self) -> PacketSyncType;
-    
-    fn get_packet_id(&self) -> u8;
-}
-
-pub trait Parseable {
-    fn from_bytes(&self, data: Bytes) -> Self;
-}
 
-pub trait Packable {
-    fn to_bytes(&self) -> Bytes;
-}
-
-impl Packable for Packet {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
+    // Next delay, with up to 20% jitter either way so several daemons
+    // sharing a hub don't retry in lockstep
+    fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = std::cmp::min(self.delay * 2, MAX_BACKOFF);
 
-pub struct EnrPacket;
-impl Packet for EnrPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Sync
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let jitter = 80 + nanos % 41;
+        delay * jitter / 100
     }
 
-    fn get_packet_id(&self) -> u8 {
-        0x02
+    fn reset(&mut self) {
+        self.delay = MIN_BACKOFF;
     }
 }
 
-#[derive(Debug)]
-pub struct AuthPacket {
-    completion: u8,
-}
-impl AuthPacket {
-    pub fn create_done() -> AuthPacket {
-        AuthPacket {
-            completion: 0xFF,
+// Tells sinks the daemon is going offline before the default panic output.
+// Only a panic on the main thread takes the daemon down; one on a sink or
+// client thread just ends that thread.
+fn report_panics(sinks: Vec<Arc<dyn Sink>>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if thread::current().name() != Some("main") {
+            default_hook(info);
+            return;
         }
-    }
-    
-    pub fn create_blinking() -> AuthPacket {
-        AuthPacket {
-            completion: 0x00,
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "unknown".to_string(),
+            },
+        };
+        for sink in &sinks {
+            sink.shutdown(&ShutdownReason::Panic(message.clone()));
         }
-    }
-}
-impl Packet for AuthPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x14
-    }
-}
-
-impl Packable for AuthPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(2);
-        buf.put_u8(self.get_packet_id());
-        buf.put_u8(self.completion);
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct GetMacPacket;
-impl Packet for GetMacPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Sync
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x04
-    }
-}
-
-impl Packable for GetMacPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct GetKeyPacket;
-impl Packet for GetKeyPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Sync
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x06
-    }
-}
-
-#[derive(Debug)]
-pub struct InquiryPacket;
-impl Packet for InquiryPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Sync
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x27
-    }
-}
-
-impl Packable for InquiryPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct GetVerPacket;
-impl Packet for GetVerPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x16
-    }
-}
-
-impl Packable for GetVerPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct GetSensorCountPacket;
-impl Packet for GetSensorCountPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x2E
-    }
-}
-
-impl Packable for GetSensorCountPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct SetRandomPacket {
-    data: [u8; 16],
-}
-impl Packet for SetRandomPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x21
-    }
-}
-
-impl Packable for SetRandomPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(17);
-        buf.put_u8(self.get_packet_id());
-        buf.put_slice(&self.data);
-        buf.into()
-    }
-}
-impl SetRandomPacket {
-    pub fn create(data: [u8; 16]) -> SetRandomPacket {
-        SetRandomPacket {
-            data
-        } 
-    }
-}
-
-#[derive(Debug)]
-pub struct StartStopNetworkPacket {
-    join_mode: bool,
-}
-impl Packet for StartStopNetworkPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x1C
-    }
-}
-
-impl Packable for StartStopNetworkPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(2);
-        buf.put_u8(self.get_packet_id());
-        buf.put_u8(if self.join_mode { 0x01 } else { 0x00 });
-        buf.into()
-    }
-}
-impl StartStopNetworkPacket {
-    pub fn create(join_mode: bool) -> StartStopNetworkPacket {
-        StartStopNetworkPacket {
-            join_mode
-        } 
-    }
-}
-
-#[derive(Debug)]
-pub struct GetSensorListPacket {
-    count: u8,
-}
-
-impl Packet for GetSensorListPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x30
-    }
-}
-
-impl Packable for GetSensorListPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(2);
-        buf.put_u8(self.get_packet_id());
-        buf.put_u8(self.count);
-        buf.into()
-    }
-}
-
-impl GetSensorListPacket {
-    pub fn create(count: u8) -> GetSensorListPacket {
-        GetSensorListPacket {
-            count
-        } 
-    }
+        default_hook(info);
+    }));
 }
 
-
-// 2019-06-24 22:20:25,984 TRACE [wyze] Read 63: [3E, 55, AA, 53, 19, 35, 0, 0, 0, 0, 0, 0, 0, 0, 0E, A2, 37, 37, 37, 42, 31, 39, 36, 32, 01, 01, 00, 51, 04, 5C, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 1, 0, 51, 3D, 4, EE]
-// 2019-06-24 22:20:31,836 TRACE [wyze] Read 63: [3E, 55, AA, 53, 19, 35, 0, 0, 0, 0, 0, 0, 0, 0, 0E, A2, 37, 37, 37, 42, 31, 39, 36, 32, 01, 00, 00, 52, 04, 5C, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-#[derive(Debug)]
-pub struct SensorEventPacket {
-    // preamble, len, id:
-    // XX YY 17 35
-    // payload:
-    // 00 00 01 6A DD 39 43 80 0C A3 <37 37 37 42 31 39 36 32> <01> 10
-    // 0  1  2  3  4  5  6  7  8  9   10 11 12 13 14 15 16 17   18  19
-    // checksum:
-    // 06 5B
-
-    // timestamp ?
-    // device id (ASCII) b 10 - b17
-    // Device type b 18
-    // b 19-21?
-
-    device_id: String,
-    device_type: u8,
-}
-impl Packet for SensorEventPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x35
-    }
-}
-
-impl Packable for SensorEventPacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+// Tells the sinks why the daemon is going away, then exits
+fn stop(sinks: &[Arc<dyn Sink>], reason: &ShutdownReason) -> ! {
+    println!("Stopping: {:?}", reason);
+    for sink in sinks {
+        sink.shutdown(reason);
     }
+    std::process::exit(0);
 }
 
-
-// 2019-06-24 22:20:31,928 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:20:32,016 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:20:32,103 TRACE [wyze] Read 63: [21, 55, AA, 53, 1D, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:21:24,164 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:21:24,251 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:21:24,338 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-// 2019-06-24 22:21:24,426 TRACE [wyze] Read 63: [27, 55, AA, 53, 23, 19, 0, 0, 0, 0, 0, 0, 0, 0, AB, 37, 37, 37, 41, 43, 32, 36, 30, 2, 1, 5, 3, 5, 3, 7, 5, 0, 7, 5, 4, 0, 40, 0, 4, 69, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-#[derive(Debug)]
-pub struct SensorAlarmPacket {
-    // state, battery (% in hex), signal strength
+// Value following `name` on the command line
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next();
+    args.next()
 }
-impl Packet for SensorAlarmPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
 
-    fn get_packet_id(&self) -> u8 {
-        0x19
-    }
-}
+// --mqtt HOST[:PORT] [--mqtt-user USER] [--mqtt-password PASSWORD] [--mqtt-prefix PREFIX]
+#[cfg(feature = "mqtt")]
+fn mqtt_sink() -> Option<Arc<dyn Sink>> {
+    use wyze::mqtt::{MqttConfig, MqttSink};
 
-impl Packable for SensorAlarmPacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+    let broker = arg_value("--mqtt")?;
+    let mut config = MqttConfig::default();
+    match broker.rfind(':') {
+        Some(colon) => {
+            config.host = broker[..colon].to_string();
+            config.port = match broker[colon + 1..].parse() {
+                Ok(port) => port,
+                Err(_) => {
+                    println!("Invalid MQTT port in {}", broker);
+                    std::process::exit(2);
+                }
+            };
+        }
+        None => config.host = broker,
     }
-}
-
-#[derive(Debug)]
-pub struct SensorScanPacket {
-    // Stuff
-}
-impl Packet for SensorScanPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
+    config.username = arg_value("--mqtt-user");
+    config.password = arg_value("--mqtt-password");
+    if let Some(prefix) = arg_value("--mqtt-prefix") {
+        config.topic_prefix = prefix;
     }
 
-    fn get_packet_id(&self) -> u8 {
-        0x20
-    }
+    Some(Arc::new(MqttSink::connect(config)))
 }
 
-impl Packable for SensorScanPacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+#[cfg(not(feature = "mqtt"))]
+fn mqtt_sink() -> Option<Arc<dyn Sink>> {
+    if std::env::args().any(|arg| arg == "--mqtt") {
+        println!("Built without MQTT support, ignoring --mqtt");
     }
+    None
 }
 
-// 2019-06-24 22:20:57,659 TRACE [wyze] Read 63: [7, 55, AA, 53, 3, 32, 1, 87, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5, 19, 0, 0, 0, 0, 0, 0, 0, 0, A2, 37, 37, 37, 42, 31, 39, 36, 32, 1, 1A, 60, 0, 1, 0, 0, 52, 44, 4, F5]
-#[derive(Debug)]
-pub struct SensorNotifySyncTimePacket {
-    // Stuff
-}
-impl Packet for SensorNotifySyncTimePacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
+// --leak-dry-readings N [--leak-dry-secs S]
+fn leak_hysteresis() -> Option<LeakHysteresis> {
+    let readings = arg_value("--leak-dry-readings");
+    let secs = arg_value("--leak-dry-secs");
+    if readings.is_none() && secs.is_none() {
+        return None;
     }
 
-    fn get_packet_id(&self) -> u8 {
-        0x32
-    }
+    let parse = |value: Option<String>, default: u64| match value {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            println!("Invalid leak hysteresis setting: {}", value);
+            std::process::exit(2);
+        }),
+        None => default,
+    };
+    Some(LeakHysteresis::new(
+        parse(readings, 1) as u32,
+        Duration::from_secs(parse(secs, 0)),
+    ))
 }
 
-impl Packable for SensorNotifySyncTimePacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+// --dedupe-secs S, 0 to pass on every repeat
+fn alarm_dedupe() -> Option<AlarmDedupe> {
+    let window = match arg_value("--dedupe-secs") {
+        Some(secs) => match secs.parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                println!("Invalid dedupe window: {}", secs);
+                std::process::exit(2);
+            }
+        },
+        None => DEDUPE_WINDOW,
+    };
+    if window == Duration::from_secs(0) {
+        return None;
+    }
+    Some(AlarmDedupe::new(window))
+}
+
+// --sample-every N [--sample-file PATH]: keeps one in N frames of each
+// command id, for protocol research
+fn sampler() -> Option<Arc<Sampler>> {
+    let every = arg_value("--sample-every")?;
+    let every = match every.parse() {
+        Ok(every) if every > 0 => every,
+        _ => {
+            println!("Invalid sampling rate: {}", every);
+            std::process::exit(2);
+        }
+    };
+    let path = arg_value("--sample-file").unwrap_or_else(|| SAMPLE_FILE.to_string());
+    match Sampler::open(&path, every) {
+        Ok(sampler) => {
+            println!("Sampling one in {} frames to {}", every, path);
+            Some(Arc::new(sampler))
+        }
+        Err(e) => {
+            println!("Failed to open {}: {}", path, e);
+            std::process::exit(1);
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct SyncTimeResponsePacket {
-    // Stuff
-}
-impl Packet for SyncTimeResponsePacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
+// --checksum-burst N [--checksum-window S], N = 0 to never resync
+fn set_checksum_tolerance(hub: &mut OpenWyzeHub) {
+    let burst = arg_value("--checksum-burst");
+    let window = arg_value("--checksum-window");
+    if burst.is_none() && window.is_none() {
+        return;
     }
 
-    fn get_packet_id(&self) -> u8 {
-        0x33
-    }
-}
+    let parse = |value: Option<String>, default: u64| match value {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            println!("Invalid checksum tolerance setting: {}", value);
+            std::process::exit(2);
+        }),
+        None => default,
+    };
+    hub.set_checksum_tolerance(
+        parse(burst, wyze::DEFAULT_CHECKSUM_BURST as u64) as usize,
+        Duration::from_secs(parse(window, wyze::DEFAULT_CHECKSUM_WINDOW.as_secs())),
+    );
+}
+
+// "1:5" or "001:005", as shown by lsusb
+fn parse_bus_address(s: &str) -> Option<(u8, u8)> {
+    let mut parts = s.splitn(2, ':');
+    let bus = parts.next()?.parse().ok()?;
+    let address = parts.next()?.parse().ok()?;
+    Some((bus, address))
+}
+
+fn open_hub<'a>(
+    hub: WyzeHub<'a>,
+    monitor_only: bool,
+    dry_run: bool,
+) -> Result<OpenWyzeHub<'a>, HubError> {
+    init_hub(hub.open()?, monitor_only, dry_run)
+}
+
+fn init_hub<'a>(
+    mut hub: OpenWyzeHub<'a>,
+    monitor_only: bool,
+    dry_run: bool,
+) -> Result<OpenWyzeHub<'a>, HubError> {
+    if monitor_only {
+        println!("Monitor-only mode");
+        hub.set_monitor_only(true);
+    }
+    if dry_run {
+        println!("Dry run, nothing will be sent");
+        hub.set_dry_run(true);
+    }
+    hub.init()?;
+    Ok(hub)
+}
+
+// Opens and initialises the bridge picked by --bridge <mac|serial|bus:addr>,
+// or the first one if no bridge was asked for. Matching by mac asks each
+// bridge for it in turn; only the one that matches is reset and
+// initialised. Bridges that fail to open are skipped.
+fn open_selected<'a>(
+    hubs: Vec<WyzeHub<'a>>,
+    selector: Option<&str>,
+    monitor_only: bool,
+    dry_run: bool,
+) -> Option<OpenWyzeHub<'a>> {
+    let selector = match selector {
+        Some(selector) => selector,
+        None => {
+            println!("Selecting first bridge");
+            let hub = hubs.into_iter().next()?;
+            return open_hub(hub, monitor_only, dry_run)
+                .map_err(|e| println!("Failed to open bridge: {}", e))
+                .ok();
+        }
+    };
+
+    for hub in hubs {
+        let (bus, address) = hub.bus_address();
+        if parse_bus_address(selector) == Some((bus, address))
+            || hub.serial().as_deref() == Some(selector)
+        {
+            println!("Selecting bridge {:03}:{:03}", bus, address);
+            return open_hub(hub, monitor_only, dry_run)
+                .map_err(|e| println!("Failed to open bridge: {}", e))
+                .ok();
+        }
 
-impl Packable for SyncTimeResponsePacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+        // Nothing is sent in monitor-only and dry-run modes, so the mac
+        // can't be asked for
+        if selector.len() == 8 && !monitor_only && !dry_run {
+            let opened = hub.open().and_then(|mut hub| {
+                let mac = hub.read_mac()?;
+                Ok((hub, mac))
+            });
+            match opened {
+                Ok((hub, mac)) if mac == selector => {
+                    println!("Selecting bridge {}", selector);
+                    return init_hub(hub, monitor_only, dry_run)
+                        .map_err(|e| println!("Failed to open bridge: {}", e))
+                        .ok();
+                }
+                Ok((_, mac)) => println!("Skipping bridge {} at {:03}:{:03}", mac, bus, address),
+                Err(e) => println!("Failed to open bridge {:03}:{:03}: {}", bus, address, e),
+            }
+        }
     }
+    None
 }
 
-#[derive(Debug)]
-pub struct AddSensorPacket {
-    // TODO: sensor MAC, type, version
-}
-impl Packet for AddSensorPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0x20
-    }
+enum Command {
+    Selftest,
+    // true to bind without asking (--yes)
+    Pair(bool),
+    Remove(String),
+    HubDiag(Option<u8>),
 }
 
-impl Packable for AddSensorPacket {
-    fn to_bytes(&self) -> Bytes {
-        // This is an incoming message
-        unimplemented!()
+// "0x40" or "64"
+fn parse_cmd_id(s: &str) -> Option<u8> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u8::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
     }
 }
 
-#[derive(Debug)]
-pub struct DeleteSensorCommandPacket {
-    // Something?
-}
-impl Packet for DeleteSensorCommandPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
+// Shows the sensor found while pairing and asks whether to bind it
+fn confirm_sensor(scan: &SensorScanPacket) -> bool {
+    println!("Found sensor {}", scan.mac);
+    println!("  type:     {:?}", scan.sensor_type);
+    println!("  firmware: 0x{:02X}", scan.version);
+    print!("Check the mac against the label on the sensor. Bind it? [y/N] ");
+    let _ = io::stdout().flush();
 
-    fn get_packet_id(&self) -> u8 {
-        0x25
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
     }
+    matches!(answer.trim(), "y" | "Y" | "yes")
 }
 
-impl Packable for DeleteSensorCommandPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(1);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
-    }
-}
-
-#[derive(Debug)]
-pub struct AckPacket {
-    for_packet_id: u8,
-}
-
-impl Packet for AckPacket {
-    fn get_packet_type(&self) -> PacketSyncType {
-        PacketSyncType::Async
-    }
-
-    fn get_packet_id(&self) -> u8 {
-        0xFF
+// Asks the daemon to redo the handshake and prints how each step went,
+// returning the exit code
+fn request_reinit() -> i32 {
+    let mut stream = match UnixStream::connect(CLIENT_SOCKET) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Failed to connect to {}: {}", CLIENT_SOCKET, e);
+            return 1;
+        }
+    };
+    if let Err(e) = stream.write_all(b"{\"method\":\"reinit\"}\n") {
+        println!("Failed to send request: {}", e);
+        return 1;
+    }
+
+    // Events are broadcast on the same socket; the answer is the first
+    // line that isn't one
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                println!("Failed to read answer: {}", e);
+                return 1;
+            }
+        };
+        let answer: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(answer) => answer,
+            Err(_) => continue,
+        };
+        if answer.get("event").is_some() {
+            continue;
+        }
+        if let Some(error) = answer.get("error") {
+            println!("Reinit failed: {}", error);
+            return 1;
+        }
+        let steps = answer["steps"].as_array().cloned().unwrap_or_default();
+        let mut failed = false;
+        for step in &steps {
+            match step["error"].as_str() {
+                Some(error) => {
+                    failed = true;
+                    println!("{}: {}", step["name"].as_str().unwrap_or("?"), error);
+                }
+                None => println!("{}: ok", step["name"].as_str().unwrap_or("?")),
+            }
+        }
+        return if failed { 1 } else { 0 };
     }
+    println!("Daemon closed the connection");
+    1
 }
 
-impl Packable for AckPacket {
-    fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(2);
-        buf.put_u8(self.for_packet_id);
-        buf.put_u8(self.get_packet_id());
-        buf.into()
+// Runs a one-shot command, returning the exit code
+fn run_command(hub: &mut OpenWyzeHub, command: Command) -> i32 {
+    match command {
+        Command::Selftest => {
+            let result = hub.selftest();
+            println!("{}", serde_json::to_string(&result).unwrap());
+            if result.passed() { 0 } else { 1 }
+        }
+        Command::Pair(yes) => match hub.start_pairing(PAIRING_TIMEOUT, |scan| {
+            yes || confirm_sensor(scan)
+        }) {
+            Ok(scan) => {
+                println!("Paired {} ({:?})", scan.mac, scan.sensor_type);
+                0
+            }
+            Err(e) => {
+                println!("Pairing failed: {}", e);
+                1
+            }
+        },
+        Command::Remove(mac) => match hub.remove_sensor(&mac) {
+            Ok(()) => {
+                println!("Removed {}", mac);
+                0
+            }
+            Err(e) => {
+                println!("Failed to remove {}: {}", mac, e);
+                1
+            }
+        },
+        Command::HubDiag(None) => {
+            let readings = hub.diagnostics();
+            for reading in &readings {
+                match (&reading.result, &reading.decoded) {
+                    (Ok(_), Some(decoded)) => {
+                        println!("{} (0x{:02X}): {}", reading.name, reading.cmd_id, decoded)
+                    }
+                    (Ok(payload), None) => {
+                        let payload = hex(payload, " ");
+                        println!("{} (0x{:02X}): {}", reading.name, reading.cmd_id, payload)
+                    }
+                    (Err(e), _) => println!("{} (0x{:02X}): {}", reading.name, reading.cmd_id, e),
+                }
+            }
+            if readings.iter().all(|reading| reading.result.is_ok()) { 0 } else { 1 }
+        }
+        Command::HubDiag(Some(cmd_id)) => {
+            let packet_type = if std::env::args().any(|arg| arg == "--sync") {
+                PacketSyncType::Sync
+            } else {
+                PacketSyncType::Async
+            };
+            match hub.probe(packet_type, cmd_id, &[]) {
+                Ok(payload) => {
+                    println!("0x{:02X}: {}", cmd_id.wrapping_add(1), hex(&payload, " "));
+                    0
+                }
+                Err(e) => {
+                    println!("0x{:02X}: {}", cmd_id, e);
+                    1
+                }
+            }
+        }
     }
 }
 
-impl<'a> OpenWyzeHub<'a> {
-    pub fn init(&mut self) {
-        trace!("Reset");
-        self.handle.reset().unwrap();
-
-        trace!("Set active config");
-        self.handle.set_active_configuration(0x00).unwrap();
-
-        trace!("Claim interface");
-        self.handle.claim_interface(0x0000).unwrap();
-
-        trace!("USB HID setup complete");
-
-        self.send(InquiryPacket);
-        let _ = self.raw_read();
-
-        self.send(GetMacPacket);
-        let _ = self.raw_read();
-        
-        self.send(GetVerPacket);
-        let _ = self.raw_read();
-        
-        self.send(GetSensorCountPacket);
-        let _ = self.raw_read();
-        
-        self.send(GetSensorListPacket::create(5));
-        let _ = self.raw_read();
-        let _ = self.raw_read();
-        let _ = self.raw_read();
-
-        self.send(AuthPacket::create_done());
-
-        trace!("Hub setup complete");
+fn main() {
+    simple_logger::init().unwrap();
 
-        loop {
-            let _ = self.raw_read();
+    // One-shot commands, run against the bridge and then exit non-zero on
+    // failure:
+    //   selftest: machine readable check of the TX/RX path
+    //   pair [--yes]: pairs the first sensor that shows up, once its mac,
+    //     type and firmware are confirmed at a prompt (or right away with
+    //     --yes)
+    //   remove <mac>: unbinds a sensor
+    //   hub-diag [--probe ID [--sync]]: prints what the dongle reports about
+    //     itself, or the raw answer to an arbitrary command. Firmware update
+    //     and delete commands are refused.
+    // pair and remove print the frames instead of sending them with --dry-run
+    //
+    // reinit asks the running daemon to redo the handshake instead, over
+    // the client socket
+    let command = std::env::args().nth(1);
+    if command.as_deref() == Some("reinit") {
+        std::process::exit(request_reinit());
+    }
+    let command = match command.as_deref() {
+        Some("selftest") => Some(Command::Selftest),
+        Some("pair") => Some(Command::Pair(std::env::args().any(|arg| arg == "--yes"))),
+        Some("remove") => match std::env::args().nth(2) {
+            Some(mac) => Some(Command::Remove(mac)),
+            None => {
+                println!("Usage: remove <mac>");
+                std::process::exit(2);
+            }
+        },
+        Some("hub-diag") => match arg_value("--probe") {
+            Some(id) => match parse_cmd_id(&id) {
+                Some(id) => Some(Command::HubDiag(Some(id))),
+                None => {
+                    println!("Invalid command id: {}", id);
+                    std::process::exit(2);
+                }
+            },
+            None => Some(Command::HubDiag(None)),
+        },
+        _ => None,
+    };
+    let one_shot = command.is_some();
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+
+    // Each sink runs on its own thread, so a slow one (e.g. MQTT with the
+    // broker down) doesn't delay the others
+    let mut sinks: Vec<Arc<dyn Sink>> = vec![];
+    let (reinit, reinit_requests) = mpsc::channel();
+    let reinit_requests = Arc::new(Mutex::new(reinit_requests));
+    if !one_shot {
+        match ClientSink::new(reinit) {
+            Ok(clients) => sinks.push(Arc::new(QueuedSink::new("clients", Arc::new(clients)))),
+            Err(e) => {
+                println!("Failed to listen on {}: {}", CLIENT_SOCKET, e);
+                std::process::exit(1);
+            }
         }
-    }
-
-    fn send<P>(&self, packet: P)
-        where P: Packet + Packable + Debug
-    {
-        trace!("Sending packet {:?}", packet);
-        let mut write: Vec<u8> = Vec::new();
-        let data = packet.to_bytes();
-
-        // Direction
-        write.extend(&[0xAA, 0x55]);
-
-        // Type
-        match packet.get_packet_type() {
-            PacketSyncType::Sync => write.push(0x43),
-            PacketSyncType::Async => write.push(0x53),
+        if let Some(mqtt) = mqtt_sink() {
+            sinks.push(Arc::new(QueuedSink::new("mqtt", mqtt)));
         }
-
-        // Length
-        write.push(data.len() as u8 + 2);
-
-        // payload
-        write.extend(data);
-
-        // checksum
-        let ck: u16 = write.iter().fold(0u16, |acc, x| acc.wrapping_add(*x as u16));
-        let ck_bytes: &[u8] = &[(ck >> 8 & 0xFF) as u8, (ck & 0xFF) as u8];
-        write.extend(ck_bytes);
-
-        self.raw_write(write);
+        report_panics(sinks.clone());
+        signal::install();
     }
 
-    fn raw_write(&self, data: Vec<u8>) {
-        trace!("Sending data {:x?}", &data);
-
-        self.handle
-            .write_control(
-                0x21,   // LIBUSB_REQUEST_TYPE_CLASS | LIBUSB_RECIPIENT_INTERFACE | LIBUSB_ENDPOINT_OUT
-                0x09,   // HID SET_REPORT
-                0x02AA, // Report number 0xAA
-                0x0000,
-                &data,
-                std::time::Duration::new(1, 0),
-            )
-            .unwrap();
-    }
+    let context = libusb::Context::new().unwrap();
+    let monitor_only = std::env::args().any(|arg| arg == "--monitor");
+    let bridge = arg_value("--bridge");
+    let sampler = if one_shot { None } else { sampler() };
+    let mut waiting = false;
+    let mut backoff = Backoff::new();
+
+    // libusb 0.3 has no hotplug callbacks, so insertion is noticed by
+    // polling the device list. A bridge that goes away is waited for in the
+    // same way and initialised again when it comes back. A bridge that is
+    // present but fails is reopened with exponential backoff.
+    loop {
+        if let Some(signal) = signal::received() {
+            stop(&sinks, &ShutdownReason::Signal(signal));
+        }
 
-    fn raw_read(&mut self) -> Result<&[u8], ()> {
-        let rsp = self
-            .handle
-            .read_interrupt(0x82, &mut self.buf, std::time::Duration::new(1, 0));
-
-        return match rsp {
-            Ok(len) => {
-                let rsp = &self.buf[..len];
-                magic::try_parse(rsp);
-                trace!("Read {:?}: {:X?}", rsp.len(), &rsp);
-                Ok(rsp)
+        let hubs = WyzeHub::get_hubs(&context);
+        if hubs.is_empty() {
+            if one_shot {
+                println!("No bridge found");
+                std::process::exit(1);
+            }
+            if !waiting {
+                println!("Waiting for a bridge");
+                waiting = true;
+            }
+            thread::sleep(HOTPLUG_POLL);
+            continue;
+        }
+        if !waiting {
+            println!("Found {} bridge(s)", hubs.len());
+        }
+        let selector = bridge.as_deref();
+        let mut hub = match open_selected(hubs, selector, monitor_only, dry_run) {
+            Some(hub) => hub,
+            None => {
+                let name = selector.unwrap_or("bridge");
+                if one_shot {
+                    println!("Couldn't open {}", name);
+                    std::process::exit(1);
+                }
+                if !waiting {
+                    println!("Waiting for {}", name);
+                    waiting = true;
+                }
+                thread::sleep(backoff.next());
+                continue;
             }
-            Err(_) => Err(()),
         };
-    }
-}
+        waiting = false;
+        set_checksum_tolerance(&mut hub);
+        if let Some(dedupe) = alarm_dedupe() {
+            hub.add_transform(Box::new(dedupe));
+        }
+        if let Some(hysteresis) = leak_hysteresis() {
+            hub.add_transform(Box::new(hysteresis));
+        }
+        if let Some(sampler) = &sampler {
+            hub.set_sampler(sampler.clone());
+        }
+        hub.set_reinit_requests(reinit_requests.clone());
 
-fn main() {
-    simple_logger::init().unwrap();
+        if let Some(command) = command {
+            std::process::exit(run_command(&mut hub, command));
+        }
 
-    let context = libusb::Context::new().unwrap();
-    let mut hubs = WyzeHub::get_hubs(&context);
-    println!("Found {} bridge(s)", hubs.len());
-    if hubs.len() == 0 {
-        return;
+        let started = Instant::now();
+        let reason = hub.run(&sinks);
+        if let ShutdownReason::Signal(_) = reason {
+            stop(&sinks, &reason);
+        }
+        println!("Bridge lost: {:?}", reason);
+        for sink in &sinks {
+            sink.shutdown(&reason);
+        }
+
+        // Only a bridge that ran for a while is considered recovered
+        if started.elapsed() > MAX_BACKOFF {
+            backoff.reset();
+        }
+        drop(hub);
+        thread::sleep(backoff.next());
     }
-    println!("Selecting first bridge");
-    let hub = hubs.remove(0);
-    let mut hub = hub.open();
-    hub.init();
 }
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rumqttc::{Client, LastWill, MqttOptions, QoS};
+
+use crate::packet::PacketPayload;
+use crate::sink::Sink;
+use crate::ShutdownReason;
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // Topics are <prefix>/<mac>/<field> and <prefix>/status
+    pub topic_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> MqttConfig {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic_prefix: "wyze".to_string(),
+        }
+    }
+}
+
+// Publishes sensor state, battery and link quality readings to an MQTT broker.
+// <prefix>/status is retained and goes "offline" on shutdown, or through
+// the last will if the daemon dies without saying goodbye. It goes back to
+// "online" with the first payload after the bridge comes back.
+pub struct MqttSink {
+    client: Mutex<Client>,
+    prefix: String,
+    offline: AtomicBool,
+}
+
+impl MqttSink {
+    pub fn connect(config: MqttConfig) -> MqttSink {
+        let status = format!("{}/status", config.topic_prefix);
+
+        let mut options = MqttOptions::new("wyze", config.host.as_str(), config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(status.as_str(), "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = config.username {
+            options.set_credentials(username, config.password.unwrap_or_default());
+        }
+
+        let (client, mut connection) = Client::new(options, 64);
+
+        // The event loop has to be polled for anything to be sent. It
+        // reconnects by itself on errors.
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(event) => debug!("MQTT: {:?}", event),
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+
+        info!("Publishing to {}:{}", config.host, config.port);
+        let sink = MqttSink {
+            client: Mutex::new(client),
+            prefix: config.topic_prefix,
+            offline: AtomicBool::new(false),
+        };
+        sink.publish(&status, "online", true);
+        sink
+    }
+
+    fn publish(&self, topic: &str, value: &str, retain: bool) {
+        let client = match self.client.lock() {
+            Ok(client) => client,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = client.publish(topic, QoS::AtLeastOnce, retain, value.as_bytes()) {
+            warn!("Failed to publish to {}: {}", topic, e);
+        }
+    }
+
+    fn publish_field(&self, mac: &str, field: &str, value: &str) {
+        self.publish(&format!("{}/{}/{}", self.prefix, mac, field), value, true);
+    }
+}
+
+impl Sink for MqttSink {
+    fn send(&self, payload: &PacketPayload) {
+        if self.offline.swap(false, Ordering::SeqCst) {
+            self.publish(&format!("{}/status", self.prefix), "online", true);
+        }
+        match payload {
+            PacketPayload::SensorEvent(packet) => {
+                let event = &packet.event;
+                self.publish_field(&event.mac, "type", event.sensor_type.name());
+                self.publish_field(&event.mac, "state", &event.state.to_string());
+            }
+            PacketPayload::SensorAlarm(alarm) => {
+                self.publish_field(&alarm.mac, "type", alarm.sensor_type.name());
+                self.publish_field(&alarm.mac, "state", &alarm.state.to_string());
+                self.publish_field(&alarm.mac, "battery", &alarm.battery.to_string());
+                self.publish_field(&alarm.mac, "lqi", &alarm.lqi.to_string());
+            }
+            PacketPayload::SensorJoin(_)
+            | PacketPayload::SensorScan(_)
+            | PacketPayload::Unknown { .. } => (),
+        }
+    }
+
+    fn shutdown(&self, reason: &ShutdownReason) {
+        let status = format!("{}/status", self.prefix);
+        self.publish(&status, "offline", true);
+        self.offline.store(true, Ordering::SeqCst);
+        self.publish(&format!("{}/shutdown", self.prefix), reason.name(), false);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::Serialize;
+
+use crate::client::ClientMessage;
+use crate::codec::{self, hex};
+use crate::packet::PacketPayload;
+use crate::{Frame, ParseError};
+
+// One line of the sample file
+#[derive(Serialize)]
+struct Sample<'a> {
+    // Milliseconds since the epoch, host clock
+    time: u64,
+    cmd_id: u8,
+    // The whole frame as the bridge sent it, 55 AA through the checksum
+    frame: String,
+    decoded: Option<ClientMessage<'a>>,
+    error: Option<String>,
+}
+
+// Keeps one in every `every` frames of each command id, whole and with what
+// was decoded from it, as JSON lines appended to a file. Left running, this
+// builds up a labelled corpus for working out the bytes of the 0x19 and 0x35
+// payloads that aren't understood yet.
+//
+// Shared between successive OpenWyzeHubs, so the counts carry on across
+// reconnects.
+pub struct Sampler {
+    every: u64,
+    state: Mutex<State>,
+}
+
+struct State {
+    out: File,
+    // Frames seen so far per command id
+    seen: HashMap<u8, u64>,
+}
+
+impl Sampler {
+    pub fn open<P: AsRef<Path>>(path: P, every: u64) -> io::Result<Sampler> {
+        let out = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Sampler {
+            every: every.max(1),
+            state: Mutex::new(State {
+                out,
+                seen: HashMap::new(),
+            }),
+        })
+    }
+
+    // Called with every frame read from the bridge and the result of
+    // decoding it. Acks carry nothing worth keeping and are skipped.
+    pub fn offer(&self, frame: &Frame, decoded: &Result<PacketPayload, ParseError>) {
+        if frame.ack {
+            return;
+        }
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let seen = state.seen.entry(frame.cmd_id).or_insert(0);
+        *seen += 1;
+        if (*seen - 1) % self.every != 0 {
+            return;
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs() * 1000 + u64::from(since.subsec_millis()))
+            .unwrap_or(0);
+        let sample = Sample {
+            time,
+            cmd_id: frame.cmd_id,
+            frame: hex(&raw_frame(frame), ""),
+            decoded: decoded.as_ref().ok().map(ClientMessage::from),
+            error: decoded.as_ref().err().map(ParseError::to_string),
+        };
+
+        let mut line = match serde_json::to_string(&sample) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to encode sample: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        if let Err(e) = state.out.write_all(line.as_bytes()) {
+            warn!("Failed to write sample: {}", e);
+        }
+    }
+}
+
+// Frames out of the decoder are always from the bridge and had a good
+// checksum, so they can be put back together exactly
+fn raw_frame(frame: &Frame) -> Vec<u8> {
+    let mut raw = vec![0x55, 0xAA, frame.cmd_type, frame.payload.len() as u8 + 3, frame.cmd_id];
+    raw.extend_from_slice(&frame.payload);
+    let checksum = codec::checksum(&raw);
+    raw.push((checksum >> 8) as u8);
+    raw.push(checksum as u8);
+    raw
+}
+
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use libc::c_int;
+
+// Set by the handler to the last signal received, 0 until then
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn handler(signal: c_int) {
+    RECEIVED.store(signal, Ordering::SeqCst);
+}
+
+// Catches SIGINT and SIGTERM so run() can stop and the sinks can be told,
+// rather than the process dying mid-read. Only the signal number is
+// recorded; everything else happens on the main thread once it notices.
+pub fn install() {
+    let handler = handler as extern "C" fn(c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+// The signal that asked the daemon to stop, if one has arrived
+pub fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::packet::PacketPayload;
+use crate::ShutdownReason;
+
+// Somewhere decoded payloads are delivered to. Sinks are shared with the
+// panic hook, so they take &self and handle their own locking.
+//
+// send() is called from a single thread, in arrival order (see Transform).
+// A sink that hands payloads to another thread or queue must keep them in
+// that order, at least per sensor.
+pub trait Sink: Send + Sync {
+    fn send(&self, payload: &PacketPayload);
+
+    // Last call before the daemon goes offline
+    fn shutdown(&self, reason: &ShutdownReason);
+}
+
+// Payloads a QueuedSink holds for a sink that is falling behind
+pub const QUEUE_CAPACITY: usize = 64;
+
+// How long a QueuedSink's shutdown() waits for the sink to be told
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Delivery counts and latency of one QueuedSink
+#[derive(Debug, Clone, Default)]
+pub struct SinkStats {
+    pub delivered: u64,
+    // Payloads dropped because the queue was full
+    pub dropped: u64,
+    // Time from send() until the sink was done with a payload
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl SinkStats {
+    pub fn mean_latency(&self) -> Option<Duration> {
+        if self.delivered == 0 {
+            return None;
+        }
+        Some(self.total_latency / self.delivered as u32)
+    }
+}
+
+enum Job {
+    Payload(PacketPayload, Instant),
+    Shutdown(ShutdownReason, SyncSender<()>),
+}
+
+// Runs a sink on its own thread behind a bounded queue, so a slow or stuck
+// sink doesn't hold up the reader or the other sinks. send() never blocks:
+// once QUEUE_CAPACITY payloads are waiting, new ones are dropped for this
+// sink only. Payloads reach the sink in the order they were sent.
+pub struct QueuedSink {
+    name: String,
+    queue: SyncSender<Job>,
+    stats: Arc<Mutex<SinkStats>>,
+    worker: ThreadId,
+}
+
+impl QueuedSink {
+    pub fn new(name: &str, sink: Arc<dyn Sink>) -> QueuedSink {
+        QueuedSink::with_capacity(name, sink, QUEUE_CAPACITY)
+    }
+
+    pub fn with_capacity(name: &str, sink: Arc<dyn Sink>, capacity: usize) -> QueuedSink {
+        let (queue, jobs) = mpsc::sync_channel(capacity);
+        let stats = Arc::new(Mutex::new(SinkStats::default()));
+
+        let worker_stats = stats.clone();
+        let worker = thread::Builder::new()
+            .name(format!("sink-{}", name))
+            .spawn(move || deliver(sink, jobs, worker_stats))
+            .expect("failed to spawn sink thread");
+
+        QueuedSink {
+            name: name.to_string(),
+            queue,
+            stats,
+            worker: worker.thread().id(),
+        }
+    }
+
+    pub fn stats(&self) -> SinkStats {
+        lock(&self.stats).clone()
+    }
+}
+
+fn lock(stats: &Mutex<SinkStats>) -> std::sync::MutexGuard<'_, SinkStats> {
+    match stats.lock() {
+        Ok(stats) => stats,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn deliver(sink: Arc<dyn Sink>, jobs: Receiver<Job>, stats: Arc<Mutex<SinkStats>>) {
+    for job in jobs {
+        match job {
+            Job::Payload(payload, queued) => {
+                sink.send(&payload);
+                let latency = queued.elapsed();
+                let mut stats = lock(&stats);
+                stats.delivered += 1;
+                stats.total_latency += latency;
+                if latency > stats.max_latency {
+                    stats.max_latency = latency;
+                }
+            }
+            Job::Shutdown(reason, done) => {
+                sink.shutdown(&reason);
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+impl Sink for QueuedSink {
+    fn send(&self, payload: &PacketPayload) {
+        match self.queue.try_send(Job::Payload(payload.clone(), Instant::now())) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                let mut stats = lock(&self.stats);
+                // Only the first drop is logged, the count is in the stats
+                if stats.dropped == 0 {
+                    warn!("Sink {} is falling behind, dropping payloads", self.name);
+                }
+                stats.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("Sink {} has stopped, dropping {:?}", self.name, payload)
+            }
+        }
+    }
+
+    // Waits up to SHUTDOWN_GRACE for the sink to be told, behind whatever
+    // is still queued for it. A sink whose queue is full is skipped.
+    fn shutdown(&self, reason: &ShutdownReason) {
+        let stats = self.stats();
+        info!(
+            "Sink {}: {} delivered, {} dropped, mean latency {:?}, max {:?}",
+            self.name,
+            stats.delivered,
+            stats.dropped,
+            stats.mean_latency(),
+            stats.max_latency
+        );
+
+        let (done, finished) = mpsc::sync_channel(1);
+        if self.queue.try_send(Job::Shutdown(reason.clone(), done)).is_err() {
+            warn!("Sink {} is stuck, not waiting for it to shut down", self.name);
+            return;
+        }
+        // Called from the sink's own thread when it panics
+        if thread::current().id() == self.worker {
+            return;
+        }
+        if finished.recv_timeout(SHUTDOWN_GRACE).is_err() {
+            warn!("Sink {} didn't shut down in time", self.name);
+        }
+    }
+}
+
+// Hands payloads to a channel, see WyzeHub::events(). Once the receiver is
+// dropped, payloads are discarded and is_closed() says so.
+pub struct ChannelSink {
+    tx: Mutex<Sender<PacketPayload>>,
+    closed: AtomicBool,
+}
+
+impl ChannelSink {
+    pub fn new() -> (ChannelSink, Receiver<PacketPayload>) {
+        let (tx, rx) = mpsc::channel();
+        let sink = ChannelSink {
+            tx: Mutex::new(tx),
+            closed: AtomicBool::new(false),
+        };
+        (sink, rx)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Sink for ChannelSink {
+    fn send(&self, payload: &PacketPayload) {
+        let tx = match self.tx.lock() {
+            Ok(tx) => tx,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if tx.send(payload.clone()).is_err() {
+            self.closed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // The receiver sees the channel close when the sink is dropped
+    fn shutdown(&self, _reason: &ShutdownReason) {}
+}
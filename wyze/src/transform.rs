@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::trace;
+
+use crate::packet::PacketPayload;
+use crate::sensor::SensorType;
+
+// Rewrites or drops payloads between decoding and the sinks. `now` is
+// passed in rather than read so time can be faked in tests.
+//
+// Ordering: payloads go through the transforms one at a time, in the order
+// they were read from USB, and each comes out straight away or not at all.
+// A transform can't hold a payload back and release it later, so whatever
+// reaches the sinks from one sensor is always in arrival order. Debouncing
+// and coalescing have to be done by dropping, as LeakHysteresis does.
+pub trait Transform {
+    // Returns None to drop the payload
+    fn apply(&mut self, payload: PacketPayload, now: Instant) -> Option<PacketPayload>;
+
+    fn stage(&self) -> Stage {
+        Stage::Filter
+    }
+}
+
+// Where a transform runs relative to the others, whatever order they were
+// added in. Transforms in the same stage run in the order they were added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    // Drops repeats of a reading the bridge sent more than once, so that
+    // later transforms see each reading once. A repeated dry alarm mustn't
+    // count twice towards LeakHysteresis.
+    Dedupe,
+    Filter,
+}
+
+// Adds `transform` to `transforms`, keeping them sorted by stage
+pub fn insert(transforms: &mut Vec<Box<dyn Transform>>, transform: Box<dyn Transform>) {
+    let at = transforms
+        .iter()
+        .position(|t| t.stage() > transform.stage())
+        .unwrap_or(transforms.len());
+    transforms.insert(at, transform);
+}
+
+// Runs `payload` through each transform in turn
+pub fn apply_all(
+    transforms: &mut [Box<dyn Transform>],
+    payload: PacketPayload,
+    now: Instant,
+) -> Option<PacketPayload> {
+    let mut payload = payload;
+    for transform in transforms {
+        payload = transform.apply(payload, now)?;
+    }
+    Some(payload)
+}
+
+// Leak sensors flap between wet and dry as water evaporates. Wet is always
+// reported straight away, but a sensor only goes back to dry after
+// `dry_readings` consecutive dry readings spanning at least `min_dwell`.
+// Dry readings in between are dropped.
+//
+// The dwell is only checked when a reading arrives, so a sensor that goes
+// quiet stays wet until it next reports.
+//
+// Each reading arrives twice, as a sensor event (0x35) and as an alarm
+// (0x19) with the same counter. The second copy is passed or dropped along
+// with the first and doesn't count again.
+pub struct LeakHysteresis {
+    dry_readings: u32,
+    min_dwell: Duration,
+    sensors: HashMap<String, LeakState>,
+}
+
+#[derive(Default)]
+struct LeakState {
+    // Last state passed on
+    wet: bool,
+    dry_count: u32,
+    dry_since: Option<Instant>,
+    // Counter of the last reading, and whether it was passed on
+    last: Option<(u16, bool)>,
+}
+
+impl LeakHysteresis {
+    pub fn new(dry_readings: u32, min_dwell: Duration) -> LeakHysteresis {
+        LeakHysteresis {
+            dry_readings,
+            min_dwell,
+            sensors: HashMap::new(),
+        }
+    }
+}
+
+impl LeakState {
+    // Counts a new reading and returns whether it is passed on
+    fn count(&mut self, wet: bool, now: Instant, dry_readings: u32, min_dwell: Duration) -> bool {
+        if wet || !self.wet {
+            self.wet = wet;
+            self.dry_count = 0;
+            self.dry_since = None;
+            return true;
+        }
+
+        self.dry_count += 1;
+        let since = *self.dry_since.get_or_insert(now);
+        if self.dry_count >= dry_readings && now.duration_since(since) >= min_dwell {
+            self.wet = false;
+            self.dry_count = 0;
+            self.dry_since = None;
+            return true;
+        }
+        false
+    }
+}
+
+// Mac, state and counter of a reading from a leak sensor
+fn leak_reading(payload: &PacketPayload) -> Option<(&str, u8, u16)> {
+    match payload {
+        PacketPayload::SensorEvent(packet) if packet.event.sensor_type == SensorType::Leak => {
+            Some((&packet.event.mac, packet.event.state, packet.event.counter))
+        }
+        PacketPayload::SensorAlarm(alarm) if alarm.sensor_type == SensorType::Leak => {
+            Some((&alarm.mac, alarm.state, alarm.counter))
+        }
+        _ => None,
+    }
+}
+
+impl Transform for LeakHysteresis {
+    fn apply(&mut self, payload: PacketPayload, now: Instant) -> Option<PacketPayload> {
+        let (mac, wet, counter) = match leak_reading(&payload) {
+            Some((mac, state, counter)) => (mac.to_owned(), state != 0, counter),
+            None => return Some(payload),
+        };
+        let sensor = self.sensors.entry(mac).or_default();
+
+        if let Some((last, passed)) = sensor.last {
+            if last == counter {
+                trace!("Second copy of reading {}, passed: {}", counter, passed);
+                return if passed { Some(payload) } else { None };
+            }
+        }
+
+        let passed = sensor.count(wet, now, self.dry_readings, self.min_dwell);
+        sensor.last = Some((counter, passed));
+        if passed {
+            Some(payload)
+        } else {
+            trace!(
+                "Holding dry reading {} of {}: {:?}",
+                sensor.dry_count,
+                self.dry_readings,
+                payload
+            );
+            None
+        }
+    }
+}
+
+// The bridge sends each reading several times with the same counter: as a
+// sensor event (0x35) and as one or more alarms (0x19). Repeats of the last
+// counter seen from a sensor, of either kind, are dropped for `window` after
+// it was first seen, so each state change is passed on once.
+pub struct AlarmDedupe {
+    window: Duration,
+    // Last counter passed on per sensor mac, and when
+    last: HashMap<String, (u16, Instant)>,
+}
+
+impl AlarmDedupe {
+    pub fn new(window: Duration) -> AlarmDedupe {
+        AlarmDedupe {
+            window,
+            last: HashMap::new(),
+        }
+    }
+}
+
+impl Transform for AlarmDedupe {
+    fn apply(&mut self, payload: PacketPayload, now: Instant) -> Option<PacketPayload> {
+        let (mac, counter) = match &payload {
+            PacketPayload::SensorEvent(packet) => (&packet.event.mac, packet.event.counter),
+            PacketPayload::SensorAlarm(alarm) => (&alarm.mac, alarm.counter),
+            _ => return Some(payload),
+        };
+
+        if let Some(&(last, seen)) = self.last.get(mac) {
+            if last == counter && now.duration_since(seen) < self.window {
+                trace!("Dropping repeated reading {} from {}", counter, mac);
+                return None;
+            }
+        }
+        self.last.insert(mac.clone(), (counter, now));
+        Some(payload)
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Dedupe
+    }
+}
@@ -0,0 +1,175 @@
+// Commands go through link::transact() to a MockDongle that misbehaves the
+// way a Scenario says.
+#![cfg(feature = "fault-injection")]
+
+use std::time::{Duration, Instant};
+
+use wyze::codec::{encode, encode_bridge};
+use wyze::fault::{MockDongle, Scenario};
+use wyze::link;
+use wyze::packet::{PacketSyncType, RawPacket};
+use wyze::{FrameDecoder, HubError};
+
+const TIMEOUT: Duration = Duration::from_millis(20);
+
+fn command(packet_type: PacketSyncType) -> Vec<u8> {
+    encode(&RawPacket::create(packet_type, 0x0C, &[0x01, 0x02]))
+}
+
+// A report holding the given bridge frames
+fn report(frames: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut data = vec![];
+    for &(cmd_id, payload) in frames {
+        data.extend(encode_bridge(&RawPacket::create(PacketSyncType::Async, cmd_id, payload)));
+    }
+    let mut report = vec![data.len() as u8];
+    report.extend(data);
+    report.resize(64, 0);
+    report
+}
+
+#[test]
+fn answered_command_returns_response() {
+    let mut dongle = MockDongle::new(Scenario::default());
+    let mut decoder = FrameDecoder::default();
+
+    let frame = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Async),
+        0x0D,
+        TIMEOUT,
+        2,
+        &mut vec![],
+    )
+    .unwrap();
+    assert_eq!(frame.cmd_id, 0x0D);
+    assert!(!frame.ack);
+    assert_eq!(frame.payload, vec![0x01, 0x02]);
+    assert_eq!(dongle.commands().len(), 1);
+}
+
+#[test]
+fn dongle_killed_mid_command_fails_fast() {
+    let mut dongle = MockDongle::new(Scenario {
+        kill: 100,
+        ..Scenario::default()
+    });
+    let mut decoder = FrameDecoder::default();
+
+    let started = Instant::now();
+    let result = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Async),
+        0x0D,
+        Duration::from_secs(5),
+        2,
+        &mut vec![],
+    );
+
+    match result {
+        Err(e @ HubError::Usb(libusb::Error::NoDevice)) => assert!(e.is_fatal()),
+        other => panic!("expected NoDevice, got {:?}", other),
+    }
+    // Neither waited out the timeout nor retransmitted to a dead dongle
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(dongle.commands().len(), 1);
+    assert_eq!(dongle.faults().killed, 1);
+    assert!(dongle.is_dead());
+}
+
+#[test]
+fn unanswered_command_is_retransmitted() {
+    let mut dongle = MockDongle::new(Scenario {
+        drop: 100,
+        ..Scenario::default()
+    });
+    let mut decoder = FrameDecoder::default();
+
+    let result = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Sync),
+        0x0D,
+        TIMEOUT,
+        2,
+        &mut vec![],
+    );
+
+    match result {
+        Err(HubError::Timeout { cmd_id: 0x0D }) => (),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert_eq!(dongle.commands().len(), 3);
+    assert_eq!(dongle.faults().dropped, 3);
+}
+
+#[test]
+fn split_response_is_reassembled() {
+    let mut dongle = MockDongle::new(Scenario {
+        seed: 3,
+        split: 100,
+        ..Scenario::default()
+    });
+    let mut decoder = FrameDecoder::default();
+
+    let frame = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Async),
+        0x0D,
+        TIMEOUT,
+        2,
+        &mut vec![],
+    )
+    .unwrap();
+    assert_eq!(frame.cmd_id, 0x0D);
+    assert_eq!(dongle.commands().len(), 1);
+    assert_eq!(dongle.faults().split, 1);
+}
+
+#[test]
+fn event_while_waiting_is_kept() {
+    let mut dongle = MockDongle::new(Scenario::default());
+    let mut decoder = FrameDecoder::default();
+    dongle.push_report(&report(&[(0x35, &[0xAB])]));
+
+    let mut unmatched = vec![];
+    let frame = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Async),
+        0x0D,
+        TIMEOUT,
+        2,
+        &mut unmatched,
+    )
+    .unwrap();
+    assert_eq!(frame.cmd_id, 0x0D);
+    // The ack isn't kept, the event is
+    let kept: Vec<_> = unmatched.iter().map(|f| (f.cmd_id, f.payload.clone())).collect();
+    assert_eq!(kept, vec![(0x35, vec![0xAB])]);
+}
+
+#[test]
+fn event_after_response_in_same_report_is_kept() {
+    let mut dongle = MockDongle::new(Scenario::default());
+    let mut decoder = FrameDecoder::default();
+    dongle.push_report(&report(&[(0x0D, &[0x01, 0x02]), (0x35, &[0xAB])]));
+
+    let mut unmatched = vec![];
+    let frame = link::transact(
+        &mut dongle,
+        &mut decoder,
+        &command(PacketSyncType::Sync),
+        0x0D,
+        TIMEOUT,
+        2,
+        &mut unmatched,
+    )
+    .unwrap();
+    assert_eq!(frame.cmd_id, 0x0D);
+    let kept: Vec<_> = unmatched.iter().map(|f| (f.cmd_id, f.payload.clone())).collect();
+    assert_eq!(kept, vec![(0x35, vec![0xAB])]);
+}
@@ -0,0 +1,151 @@
+// Reports go in through PipelineHarness::feed() and come out of the sinks,
+// decoded and transformed the same way the daemon does it.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use wyze::harness::PipelineHarness;
+use wyze::packet::{PacketPayload, SensorAlarmPacket, SensorEvent, SensorEventPacket};
+use wyze::sensor::SensorType;
+use wyze::sink::ChannelSink;
+use wyze::transform::AlarmDedupe;
+use wyze::ShutdownReason;
+
+// A HID report holding `frames`: <count> <data> <padding to 64 bytes>
+fn report(frames: &[u8]) -> Vec<u8> {
+    assert!(frames.len() < 64, "{} bytes don't fit in one report", frames.len());
+    let mut report = vec![frames.len() as u8];
+    report.extend_from_slice(frames);
+    report.resize(64, 0);
+    report
+}
+
+fn alarm(mac: &str, state: u8) -> PacketPayload {
+    PacketPayload::SensorAlarm(SensorAlarmPacket {
+        timestamp: 1_561_414_857_659,
+        mac: mac.to_string(),
+        sensor_type: SensorType::Contact,
+        type_guessed: false,
+        state,
+        battery: 0x60,
+        lqi: 0x44,
+        counter: 0x52,
+    })
+}
+
+fn event(mac: &str, state: u8) -> PacketPayload {
+    PacketPayload::SensorEvent(SensorEventPacket {
+        event: SensorEvent {
+            timestamp: 1_561_414_857_659,
+            mac: mac.to_string(),
+            sensor_type: SensorType::Contact,
+            type_guessed: false,
+            state,
+            counter: 0x51,
+        },
+    })
+}
+
+#[test]
+fn alarms_reach_sinks_with_events() {
+    let mut harness = PipelineHarness::new();
+    let sink = harness.add_sink();
+    let (channel, rx) = ChannelSink::new();
+    harness.register_sink(Arc::new(channel));
+
+    let sent = vec![alarm("777B1962", 1), event("777B1962", 0)];
+    let frames: Vec<u8> = sent.iter().flat_map(PacketPayload::pack).collect();
+    harness.feed(&report(&frames));
+
+    assert_eq!(sink.received(), sent);
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), sent);
+}
+
+#[test]
+fn channel_sink_closes_with_receiver() {
+    let (channel, rx) = ChannelSink::new();
+    let mut harness = PipelineHarness::new();
+    let channel = Arc::new(channel);
+    harness.register_sink(channel.clone());
+
+    harness.feed(&report(&alarm("777B1962", 1).pack()));
+    assert!(!channel.is_closed());
+
+    drop(rx);
+    harness.feed(&report(&alarm("777B1962", 0).pack()));
+    assert!(channel.is_closed());
+}
+
+#[test]
+fn frame_split_across_reports_is_delivered_once() {
+    let mut harness = PipelineHarness::new();
+    let sink = harness.add_sink();
+
+    let frames = alarm("777B1962", 1).pack();
+    let (head, tail) = frames.split_at(10);
+    harness.feed(&report(head));
+    assert!(sink.received().is_empty());
+    harness.feed(&report(tail));
+
+    assert_eq!(sink.received(), vec![alarm("777B1962", 1)]);
+    assert_eq!(harness.corrupt_frames(), 0);
+}
+
+#[test]
+fn repeated_alarms_are_deduped_on_the_way_to_sinks() {
+    let mut harness = PipelineHarness::new();
+    harness.add_transform(Box::new(AlarmDedupe::new(Duration::from_secs(5))));
+    let sink = harness.add_sink();
+
+    let frames = alarm("777B1962", 1).pack();
+    harness.feed(&report(&frames));
+    harness.feed(&report(&frames));
+    harness.feed(&report(&event("777B1962", 1).pack()));
+    assert_eq!(sink.received(), vec![alarm("777B1962", 1), event("777B1962", 1)]);
+
+    // Past the window the same counter is passed on again
+    harness.advance(Duration::from_secs(5));
+    harness.feed(&report(&frames));
+    assert_eq!(sink.received().len(), 3);
+}
+
+#[test]
+fn corrupt_frame_is_dropped_and_the_next_delivered() {
+    let mut harness = PipelineHarness::new();
+    let sink = harness.add_sink();
+
+    let mut corrupt = alarm("777B1962", 1).pack();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xFF;
+    harness.feed(&report(&corrupt));
+    harness.feed(&report(&alarm("77A5E2B8", 0).pack()));
+
+    assert_eq!(sink.received(), vec![alarm("77A5E2B8", 0)]);
+    assert_eq!(harness.corrupt_frames(), 1);
+}
+
+#[test]
+fn received_from_filters_by_sensor() {
+    let mut harness = PipelineHarness::new();
+    let sink = harness.add_sink();
+
+    // An alarm takes up half a report, so each gets its own
+    for payload in &[alarm("777B1962", 1), alarm("77A5E2B8", 1), event("777B1962", 0)] {
+        harness.feed(&report(&payload.pack()));
+    }
+
+    assert_eq!(
+        sink.received_from("777B1962"),
+        vec![alarm("777B1962", 1), event("777B1962", 0)]
+    );
+}
+
+#[test]
+fn shutdown_reaches_sinks() {
+    let mut harness = PipelineHarness::new();
+    let sink = harness.add_sink();
+    assert_eq!(sink.shutdown_reason(), None);
+
+    harness.shutdown(&ShutdownReason::Signal(15));
+    assert_eq!(sink.shutdown_reason(), Some(ShutdownReason::Signal(15)));
+}
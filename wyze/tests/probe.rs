@@ -0,0 +1,22 @@
+// hub-diag --probe must never send the commands that can brick the dongle or
+// unpair a sensor.
+
+use wyze::{check_probe, CommandId, HubError, UNPROBEABLE};
+
+#[test]
+fn dangerous_ids_are_refused() {
+    for &id in &[0x0E, 0x12, 0x25] {
+        match check_probe(id) {
+            Err(HubError::InvalidArgument(_)) => (),
+            other => panic!("probe of {:02X} allowed: {:?}", id, other),
+        }
+    }
+    assert!(UNPROBEABLE.contains(&CommandId::DeleteSensor));
+}
+
+#[test]
+fn other_ids_are_allowed() {
+    for id in (0..=0xFF).filter(|id| ![0x0E, 0x12, 0x25].contains(id)) {
+        assert!(check_probe(id).is_ok(), "probe of {:02X} refused", id);
+    }
+}
@@ -0,0 +1,107 @@
+// A sink that never returns must not hold up send() or the other sinks.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use wyze::harness::RecordingSink;
+use wyze::packet::{PacketPayload, SensorScanPacket};
+use wyze::sensor::SensorType;
+use wyze::sink::{QueuedSink, Sink, SHUTDOWN_GRACE};
+use wyze::ShutdownReason;
+
+// Blocks forever in every call
+struct HangingSink;
+
+impl Sink for HangingSink {
+    fn send(&self, _payload: &PacketPayload) {
+        loop {
+            thread::park();
+        }
+    }
+
+    fn shutdown(&self, _reason: &ShutdownReason) {
+        loop {
+            thread::park();
+        }
+    }
+}
+
+fn scan(version: u8) -> PacketPayload {
+    PacketPayload::SensorScan(SensorScanPacket {
+        mac: "777B1962".to_string(),
+        sensor_type: SensorType::Contact,
+        version,
+    })
+}
+
+// Polls until `sink` has received `count` payloads
+fn wait_for(sink: &RecordingSink, count: usize) -> Vec<PacketPayload> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while sink.received().len() < count && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+    sink.received()
+}
+
+#[test]
+fn hanging_sink_doesnt_delay_others() {
+    let hanging = QueuedSink::with_capacity("hanging", Arc::new(HangingSink), 4);
+    let recording = Arc::new(RecordingSink::default());
+    let queued = QueuedSink::new("recording", recording.clone());
+
+    let sent: Vec<_> = (0..32).map(scan).collect();
+    let start = Instant::now();
+    for payload in &sent {
+        hanging.send(payload);
+        queued.send(payload);
+    }
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    assert_eq!(wait_for(&recording, sent.len()), sent);
+
+    let stats = queued.stats();
+    assert_eq!(stats.delivered, sent.len() as u64);
+    assert_eq!(stats.dropped, 0);
+    assert!(stats.mean_latency().is_some());
+    assert!(stats.max_latency >= stats.mean_latency().unwrap());
+}
+
+#[test]
+fn full_queue_drops_for_that_sink_only() {
+    let hanging = QueuedSink::with_capacity("hanging", Arc::new(HangingSink), 4);
+    for version in 0..10 {
+        hanging.send(&scan(version));
+    }
+
+    // Four are queued, one more if the sink thread has already taken the
+    // first, and the rest are dropped
+    let stats = hanging.stats();
+    assert_eq!(stats.delivered, 0);
+    assert!(stats.dropped == 5 || stats.dropped == 6);
+    assert_eq!(stats.mean_latency(), None);
+}
+
+#[test]
+fn shutdown_doesnt_wait_forever() {
+    let hanging = QueuedSink::with_capacity("hanging", Arc::new(HangingSink), 4);
+    hanging.send(&scan(0));
+
+    let start = Instant::now();
+    hanging.shutdown(&ShutdownReason::Panic("test".to_string()));
+    assert!(start.elapsed() < SHUTDOWN_GRACE + Duration::from_secs(1));
+}
+
+#[test]
+fn shutdown_follows_queued_payloads() {
+    let recording = Arc::new(RecordingSink::default());
+    let queued = QueuedSink::new("recording", recording.clone());
+    for version in 0..8 {
+        queued.send(&scan(version));
+    }
+
+    let reason = ShutdownReason::Panic("test".to_string());
+    queued.shutdown(&reason);
+    assert_eq!(recording.received().len(), 8);
+    assert_eq!(recording.shutdown_reason(), Some(reason));
+}
@@ -0,0 +1,260 @@
+// Transforms run on their own and through PipelineHarness, with the clock
+// moved by hand.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use wyze::harness::{PipelineHarness, RecordingSink};
+use wyze::packet::{PacketPayload, SensorAlarmPacket, SensorEvent, SensorEventPacket};
+use wyze::sensor::SensorType;
+use wyze::sink::QueuedSink;
+use wyze::transform::{AlarmDedupe, LeakHysteresis, Transform};
+use wyze::ShutdownReason;
+
+const DWELL: Duration = Duration::from_secs(60);
+
+fn leak(mac: &str, state: u8, counter: u16) -> PacketPayload {
+    PacketPayload::SensorAlarm(SensorAlarmPacket {
+        timestamp: 1_561_414_857_659,
+        mac: mac.to_string(),
+        sensor_type: SensorType::Leak,
+        type_guessed: false,
+        state,
+        battery: 0x60,
+        lqi: 0x44,
+        counter,
+    })
+}
+
+// The 0x35 copy of a reading sent along with its alarm
+fn leak_event(mac: &str, state: u8, counter: u16) -> PacketPayload {
+    PacketPayload::SensorEvent(SensorEventPacket {
+        event: SensorEvent {
+            timestamp: 1_561_414_857_659,
+            mac: mac.to_string(),
+            sensor_type: SensorType::Leak,
+            type_guessed: false,
+            state,
+            counter,
+        },
+    })
+}
+
+// States passed on by `transform` for readings at the given offsets
+fn states(transform: &mut dyn Transform, readings: &[(u64, u8)]) -> Vec<u8> {
+    let start = Instant::now();
+    readings
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &(secs, state))| {
+            let now = start + Duration::from_secs(secs);
+            transform.apply(leak("777B1962", state, i as u16), now)
+        })
+        .map(|payload| match payload {
+            PacketPayload::SensorAlarm(alarm) => alarm.state,
+            other => panic!("unexpected {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn wet_passes_straight_away() {
+    let mut hysteresis = LeakHysteresis::new(3, DWELL);
+    assert_eq!(states(&mut hysteresis, &[(0, 0), (1, 1)]), vec![0, 1]);
+}
+
+#[test]
+fn dry_needs_enough_readings() {
+    let mut hysteresis = LeakHysteresis::new(3, Duration::from_secs(0));
+    assert_eq!(
+        states(&mut hysteresis, &[(0, 1), (1, 0), (2, 0), (3, 0), (4, 0)]),
+        vec![1, 0, 0]
+    );
+}
+
+#[test]
+fn dry_needs_dwell() {
+    let mut hysteresis = LeakHysteresis::new(2, DWELL);
+    // Enough readings, but only 30s apart until the last one
+    assert_eq!(
+        states(&mut hysteresis, &[(0, 1), (10, 0), (40, 0), (70, 0)]),
+        vec![1, 0]
+    );
+}
+
+#[test]
+fn flapping_stays_wet() {
+    let mut hysteresis = LeakHysteresis::new(2, DWELL);
+    // Each wet reading starts the count and the dwell over
+    assert_eq!(
+        states(
+            &mut hysteresis,
+            &[(0, 1), (10, 0), (20, 1), (30, 0), (80, 1), (90, 0), (160, 0)]
+        ),
+        vec![1, 1, 1, 0]
+    );
+}
+
+#[test]
+fn event_and_alarm_count_as_one_reading() {
+    let mut hysteresis = LeakHysteresis::new(2, Duration::from_secs(0));
+    let now = Instant::now();
+    let mut passed = |payload| hysteresis.apply(payload, now).is_some();
+
+    assert!(passed(leak_event("777B1962", 1, 1)));
+    assert!(passed(leak("777B1962", 1, 1)));
+    // One dry reading, however many copies of it arrive
+    assert!(!passed(leak_event("777B1962", 0, 2)));
+    assert!(!passed(leak("777B1962", 0, 2)));
+    // The second dry reading goes through, both copies of it
+    assert!(passed(leak_event("777B1962", 0, 3)));
+    assert!(passed(leak("777B1962", 0, 3)));
+}
+
+#[test]
+fn sensors_are_tracked_separately() {
+    let mut hysteresis = LeakHysteresis::new(2, Duration::from_secs(0));
+    let now = Instant::now();
+    assert!(hysteresis.apply(leak("777B1962", 1, 1), now).is_some());
+    assert!(hysteresis.apply(leak("777B1962", 0, 2), now).is_none());
+    // Never wet, so dry passes
+    assert!(hysteresis.apply(leak("77A5E2B8", 0, 1), now).is_some());
+}
+
+#[test]
+fn dedupe_runs_before_hysteresis() {
+    let mut harness = PipelineHarness::new();
+    // Added in the wrong order on purpose
+    harness.add_transform(Box::new(LeakHysteresis::new(2, Duration::from_secs(0))));
+    harness.add_transform(Box::new(AlarmDedupe::new(DWELL)));
+    let sink = harness.add_sink();
+
+    harness.feed_payload(leak("777B1962", 1, 1));
+    // One dry reading sent twice by the bridge mustn't count as two
+    harness.feed_payload(leak("777B1962", 0, 2));
+    harness.feed_payload(leak("777B1962", 0, 2));
+    assert_eq!(sink.received(), vec![leak("777B1962", 1, 1)]);
+
+    harness.feed_payload(leak("777B1962", 0, 3));
+    assert_eq!(
+        sink.received(),
+        vec![leak("777B1962", 1, 1), leak("777B1962", 0, 3)]
+    );
+}
+
+#[test]
+fn dedupe_drops_event_and_alarm_copies() {
+    let mut harness = PipelineHarness::new();
+    harness.add_transform(Box::new(AlarmDedupe::new(DWELL)));
+    let sink = harness.add_sink();
+
+    harness.feed_payload(leak_event("777B1962", 1, 1));
+    harness.feed_payload(leak("777B1962", 1, 1));
+    harness.feed_payload(leak("777B1962", 1, 1));
+    harness.feed_payload(leak("777B1962", 0, 2));
+    harness.feed_payload(leak_event("777B1962", 0, 2));
+    assert_eq!(
+        sink.received(),
+        vec![leak_event("777B1962", 1, 1), leak("777B1962", 0, 2)]
+    );
+}
+
+#[test]
+fn mixed_copies_through_dedupe_and_hysteresis() {
+    let mut harness = PipelineHarness::new();
+    harness.add_transform(Box::new(LeakHysteresis::new(2, Duration::from_secs(0))));
+    harness.add_transform(Box::new(AlarmDedupe::new(DWELL)));
+    let sink = harness.add_sink();
+
+    for &(state, counter) in &[(1, 1), (0, 2), (0, 3)] {
+        harness.feed_payload(leak_event("777B1962", state, counter));
+        harness.feed_payload(leak("777B1962", state, counter));
+    }
+    // Wet, then dry after the second dry reading, one copy each
+    assert_eq!(
+        sink.received(),
+        vec![leak_event("777B1962", 1, 1), leak_event("777B1962", 0, 3)]
+    );
+}
+
+// Counters of what reached `sink` from one sensor, in the order it arrived
+fn counters(sink: &RecordingSink, mac: &str) -> Vec<u16> {
+    sink.received_from(mac).iter().map(counter).collect()
+}
+
+fn counter(payload: &PacketPayload) -> u16 {
+    match payload {
+        PacketPayload::SensorAlarm(alarm) => alarm.counter,
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn interleaved_sensors_keep_arrival_order() {
+    let mut harness = PipelineHarness::new();
+    harness.add_transform(Box::new(AlarmDedupe::new(DWELL)));
+    harness.add_transform(Box::new(LeakHysteresis::new(2, Duration::from_secs(10))));
+    let sink = harness.add_sink();
+
+    // (mac, state, counter), with repeats and dry readings that are held
+    let readings = [
+        ("777B1962", 1, 1),
+        ("77A5E2B8", 1, 1),
+        ("777B1962", 1, 1),
+        ("777B1962", 0, 2),
+        ("77A5E2B8", 0, 2),
+        ("777B1962", 1, 3),
+        ("77A5E2B8", 0, 3),
+        ("777B1962", 0, 4),
+        ("777B1962", 0, 5),
+        ("77A5E2B8", 1, 4),
+        ("777B1962", 0, 6),
+    ];
+    for &(mac, state, counter) in &readings {
+        harness.feed_payload(leak(mac, state, counter));
+        harness.advance(Duration::from_secs(5));
+    }
+
+    assert_eq!(counters(&sink, "777B1962"), vec![1, 3, 6]);
+    assert_eq!(counters(&sink, "77A5E2B8"), vec![1, 3, 4]);
+    // Across sensors too, what got through is in arrival order
+    let all: Vec<_> = sink
+        .received()
+        .iter()
+        .map(|payload| (payload.mac().to_string(), counter(payload)))
+        .collect();
+    let expected: Vec<_> = [
+        ("777B1962", 1),
+        ("77A5E2B8", 1),
+        ("777B1962", 3),
+        ("77A5E2B8", 3),
+        ("77A5E2B8", 4),
+        ("777B1962", 6),
+    ]
+    .iter()
+    .map(|&(mac, counter)| (mac.to_string(), counter))
+    .collect();
+    assert_eq!(all, expected);
+}
+
+#[test]
+fn queued_sink_keeps_arrival_order() {
+    let mut harness = PipelineHarness::new();
+    harness.add_transform(Box::new(AlarmDedupe::new(DWELL)));
+    let recording = Arc::new(RecordingSink::default());
+    // Big enough that nothing is dropped
+    let queued = QueuedSink::with_capacity("recording", recording.clone(), 256);
+    harness.register_sink(Arc::new(queued));
+
+    for counter in 0..200 {
+        let mac = if counter % 3 == 0 { "77A5E2B8" } else { "777B1962" };
+        harness.feed_payload(leak(mac, 1, counter));
+    }
+    // Waits for everything queued before it
+    harness.shutdown(&ShutdownReason::Signal(15));
+
+    let sent: Vec<u16> = (0..200).collect();
+    let received: Vec<u16> = recording.received().iter().map(counter).collect();
+    assert_eq!(received, sent);
+    assert_eq!(recording.shutdown_reason(), Some(ShutdownReason::Signal(15)));
+}